@@ -1,105 +1,347 @@
-use std::{error::Error, string::ParseError, sync::{mpsc, Arc, Mutex}, thread, time::Duration};
+use std::{collections::HashMap, fmt, sync::{Arc, Mutex}, time::{Duration, UNIX_EPOCH, SystemTime}};
 
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
 use google_drive::{Client, AccessToken};
-use hyper::{Request, Body, Response, service::{make_service_fn, service_fn}, Server};
-use tokio::sync::oneshot::{self, Sender};
-#[derive(Debug)]
-struct TokenStruct {
-    pub state: Option<String>,
-    pub code: Option<String>,
-}
+use hyper::{Request, Body, Response, StatusCode, Server, header::{CONTENT_TYPE, HeaderValue}, service::{make_service_fn, service_fn}};
+use sha2::{Sha256, Digest};
+use tokio::sync::oneshot;
+use url::form_urlencoded;
+
+use crate::authconfig::AuthProvider;
+
+// Refresh this long before the access token actually runs out, so it doesn't expire halfway through a pull
+const REFRESH_MARGIN: u64 = 60;
+
+const GOOGLE_CONSENT_URL: &str = "https://accounts.google.com/o/oauth2/v2/auth";
+const GOOGLE_TOKEN_URL: &str = "https://oauth2.googleapis.com/token";
+const DRIVE_SCOPE: &str = "https://www.googleapis.com/auth/drive";
 
+#[derive(Clone)]
 pub(crate) struct GDStruct {
     pub token: AccessToken,
-    pub drive: Client
+    pub drive: Client,
+    // The Google account that signed in
+    pub email: Option<String>,
+    // Unix time in seconds the access token stops working at
+    pub expires_at: u64,
+    // Where the tokens came from, refreshing has to go back to the same place
+    pub provider: AuthProvider,
+}
+
+impl GDStruct {
+    pub fn new(token: AccessToken, email: Option<String>, expires_at: u64, provider: AuthProvider) -> GDStruct {
+        let drive = match &provider {
+            AuthProvider::Google { client_id, client_secret } => Client::new(client_id, client_secret, "", &token.access_token, &token.refresh_token),
+            _ => Client::new("", "", "", &token.access_token, &token.refresh_token),
+        };
+
+        GDStruct {
+            token,
+            drive,
+            email,
+            expires_at,
+            provider,
+        }
+    }
+
+    pub fn is_expired(&self) -> bool {
+        now() + REFRESH_MARGIN >= self.expires_at
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs()
+}
+
+// Turn a fresh token's expires_in into the time it expires at
+fn expires_at(token: &AccessToken) -> u64 {
+    now() + token.expires_in.max(0) as u64
+}
+
+// Whether tokens from provider can be renewed without the user. The broker keeps the client secret and only has
+// /auth and /confirm, so its refresh tokens can't be swapped for anything here
+pub(crate) fn can_refresh(provider: &AuthProvider) -> bool {
+    !matches!(provider, AuthProvider::Broker { .. })
+}
+
+// Swap the refresh token for a new access token. A service account has no refresh token, it just signs a new
+// request. A broker login that has run out is Expired, the user has to log in again
+pub(crate) async fn refresh(gds: &GDStruct) -> Result<GDStruct, LoginError> {
+    let client = reqwest::Client::new();
+
+    let mut token = match &gds.provider {
+        AuthProvider::Broker { .. } => return Err(LoginError::Expired),
+        AuthProvider::Google { client_id, client_secret } => client.post(GOOGLE_TOKEN_URL)
+            .form(&[("grant_type", "refresh_token"), ("refresh_token", &gds.token.refresh_token), ("client_id", client_id), ("client_secret", client_secret)])
+            .send().await.map_err(failed)?
+            .error_for_status().map_err(failed)?
+            .json::<AccessToken>().await.map_err(failed)?,
+        AuthProvider::ServiceAccount { key_path, subject } => service_account_token(key_path, subject).await?.0,
+    };
+
+    // Google only hands out a refresh token on the first login, the old one stays valid
+    if token.refresh_token.is_empty() {
+        token.refresh_token = gds.token.refresh_token.clone();
+    }
+
+    let expires_at = expires_at(&token);
+    Ok(GDStruct::new(token, gds.email.clone(), expires_at, gds.provider.clone()))
+}
+
+// Sign a token request with a service account key. Also returns who the token acts as
+async fn service_account_token(key_path: &str, subject: &Option<String>) -> Result<(AccessToken, String), LoginError> {
+    let key = yup_oauth2::read_service_account_key(key_path).await
+        .map_err(|e| LoginError::Failed(format!("Failed to read service account key {}: {}", key_path, e)))?;
+    let account = subject.clone().unwrap_or_else(|| key.client_email.clone());
+
+    let mut builder = yup_oauth2::ServiceAccountAuthenticator::builder(key);
+    if let Some(subject) = subject {
+        builder = builder.subject(subject.clone());
+    }
+    let authenticator = builder.build().await.map_err(failed)?;
+    let token = authenticator.token(&[DRIVE_SCOPE]).await.map_err(failed)?;
+
+    let token = AccessToken {
+        token_type: "Bearer".to_string(),
+        access_token: token.token().unwrap_or_default().to_string(),
+        expires_in: token.expiration_time().map(|t| t.unix_timestamp() - now() as i64).unwrap_or(0),
+        ..Default::default()
+    };
+    Ok((token, account))
+}
+
+// Ask Drive who the tokens belong to
+async fn account_email(tokens: &AccessToken) -> Option<String> {
+    let about = reqwest::Client::new()
+        .get("https://www.googleapis.com/drive/v3/about")
+        .query(&[("fields", "user(emailAddress)")])
+        .header("Authorization", format!("Bearer {}", tokens.access_token))
+        .send().await.ok()?
+        .json::<serde_json::Value>().await.ok()?;
+
+    about["user"]["emailAddress"].as_str().map(|v| v.to_string())
 }
 
-async fn handle_request(req: Request<Body>, tokens: Arc<Mutex<TokenStruct>>) -> Result<Response<Body>, hyper::Error> {
-    let url = req.uri();
-    let query_params: Vec<_> = url.query().unwrap_or_default().split('&').collect();
+// What came back to the loopback server: the authorization code, or why there isn't one
+type Callback = Result<String, LoginError>;
 
-    let mut tokens_guard = tokens.lock().unwrap();
+fn result_page(title: &str, message: &str, ok: bool) -> Response<Body> {
+    let color = if ok { "#0FFF50" } else { "red" };
+    let html = format!(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+    <meta charset="utf-8">
+    <title>Entangle</title>
+    <style>
+        body {{ background: #1e1e1e; color: #eeeeee; font-family: sans-serif; display: flex; align-items: center; justify-content: center; height: 100vh; margin: 0; }}
+        .card {{ border: 1px solid {color}; border-radius: 8px; padding: 32px 48px; text-align: center; }}
+        h1 {{ color: {color}; margin-top: 0; }}
+    </style>
+</head>
+<body>
+    <div class="card">
+        <h1>{title}</h1>
+        <p>{message}</p>
+    </div>
+</body>
+</html>"#,
+        color = color,
+        title = html_escape(title),
+        message = html_escape(message),
+    );
 
-    for param in query_params {
-        let key_value: Vec<_> = param.split('=').collect();
-        if key_value.len() == 2 {
-            match key_value[0] {
-                "state" => tokens_guard.state = Some(key_value[1].to_string()),
-                "code" => tokens_guard.code = Some(key_value[1].to_string()),
-                _ => {}
-            }
+    let mut response = Response::new(Body::from(html));
+    response.headers_mut().insert(CONTENT_TYPE, HeaderValue::from_static("text/html; charset=utf-8"));
+    if !ok {
+        *response.status_mut() = StatusCode::BAD_REQUEST;
+    }
+    response
+}
+
+// The message ends up in a page, and part of it comes straight from the query string
+fn html_escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+async fn handle_request(req: Request<Body>, callback: Arc<Mutex<Option<oneshot::Sender<Callback>>>>, expected_state: String) -> Result<Response<Body>, hyper::Error> {
+    let params: HashMap<String, String> = form_urlencoded::parse(req.uri().query().unwrap_or_default().as_bytes())
+        .into_owned()
+        .collect();
+
+    // Browsers also ask for things like /favicon.ico, only the redirect carries a state
+    let state = match params.get("state") {
+        Some(v) => v,
+        None => {
+            let mut response = Response::new(Body::empty());
+            *response.status_mut() = StatusCode::NOT_FOUND;
+            return Ok(response);
+        }
+    };
+
+    let result = if *state != expected_state {
+        // Whatever sent this didn't come from the login we started
+        Err(LoginError::StateMismatch)
+    }else if let Some(error) = params.get("error") {
+        Err(LoginError::Denied(error.clone()))
+    }else{
+        match params.get("code") {
+            Some(code) => Ok(code.clone()),
+            None => Err(LoginError::Failed("No authorization code in the redirect".to_string())),
         }
+    };
+
+    let page = match &result {
+        Ok(_) => result_page("Logged in to Google Drive", "You can close this tab and go back to Entangle.", true),
+        Err(e) => result_page("Login failed", &e.to_string(), false),
+    };
+
+    // Only the first redirect counts, a refresh of the page can't log in a second time
+    if let Some(sender) = callback.lock().unwrap().take() {
+        let _ = sender.send(result);
     }
 
-    Ok(Response::new(Body::from("Token received. You can now close the browser.")))
+    Ok(page)
 }
 
-pub(crate) async fn auth() -> GDStruct {
-    // Create a token structure to store state and code
-    let token_struct = Arc::new(Mutex::new(TokenStruct {
-        state: None,
-        code: None,
-    }));
+// PKCE (RFC 7636), so an intercepted authorization code is useless without the verifier that never left this app
+fn pkce_pair() -> (String, String) {
+    let verifier = format!("{}{}", uuid::Uuid::new_v4().simple(), uuid::Uuid::new_v4().simple());
+    let challenge = URL_SAFE_NO_PAD.encode(Sha256::digest(verifier.as_bytes()));
+    (verifier, challenge)
+}
+
+#[derive(Debug)]
+pub(crate) enum LoginError {
+    TimedOut,
+    Cancelled,
+    StateMismatch,
+    Denied(String),
+    Failed(String),
+    // The access token ran out and can't be refreshed
+    Expired,
+}
+
+impl fmt::Display for LoginError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoginError::TimedOut => write!(f, "Login timed out"),
+            LoginError::Cancelled => write!(f, "Login was cancelled"),
+            LoginError::StateMismatch => write!(f, "Login response did not match the login that was started"),
+            LoginError::Denied(e) => write!(f, "Google refused the login: {}", e),
+            LoginError::Failed(e) => write!(f, "Login failed: {}", e),
+            LoginError::Expired => write!(f, "The Google Drive login has expired, log in again"),
+        }
+    }
+}
+
+fn failed(e: impl fmt::Display) -> LoginError {
+    LoginError::Failed(e.to_string())
+}
+
+// Log in with provider. Browser logins give up after timeout, or as soon as anything is sent on cancel
+pub(crate) async fn auth(provider: AuthProvider, timeout: Duration, cancel: oneshot::Receiver<()>) -> Result<GDStruct, LoginError> {
+    if let AuthProvider::ServiceAccount { key_path, subject } = &provider {
+        let (token, account) = service_account_token(key_path, subject).await?;
+        let expires_at = expires_at(&token);
+        return Ok(GDStruct::new(token, Some(account), expires_at, provider));
+    }
+
+    let (callback_tx, callback_rx) = oneshot::channel::<Callback>();
+    let callback_tx = Arc::new(Mutex::new(Some(callback_tx)));
 
-    // Clone the Arc to pass to the server closure
-    let tokens = token_struct.clone();
+    let state = uuid::Uuid::new_v4().to_string();
+    let (verifier, challenge) = pkce_pair();
 
     // Define the server address
     let addr = ([127, 0, 0, 1], 0).into();
 
     // Create a make_service closure to build the server
+    let expected_state = state.clone();
     let make_svc = make_service_fn(move |_conn| {
-        let tokens = tokens.clone();
+        let callback_tx = callback_tx.clone();
+        let expected_state = expected_state.clone();
         async {
             Ok::<_, hyper::Error>(service_fn(move |req| {
-                handle_request(req, tokens.clone())
+                handle_request(req, callback_tx.clone(), expected_state.clone())
             }))
         }
     });
 
-    // Create the server
-    let server = Server::bind(&addr).serve(make_svc);
-    let actual_addr = server.local_addr().to_string();
+    // Create the server, it only lives until the login is over
+    let server = Server::try_bind(&addr).map_err(failed)?.serve(make_svc);
+    let redirect = format!("http://{}", server.local_addr());
+    let (shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
+    tokio::spawn(server.with_graceful_shutdown(async {
+        let _ = shutdown_rx.await;
+    }));
 
-    let client = reqwest::Client::new();
+    let result = async {
+        let client = reqwest::Client::new();
 
-    let state = uuid::Uuid::new_v4();
+        let user_consent_url = consent_url(&client, &provider, &redirect, &state, &challenge).await?;
 
-    let user_consent_url = client.get("https://entangleauth.eeshwar-krishnan.workers.dev/auth")
-        .query(&[("redirect", &format!("http://{}", actual_addr)), ("status", &format!("{}", state))]).send().await.unwrap().text().await.unwrap();
+        webbrowser::open(&user_consent_url).map_err(failed)?;
 
-    println!("{}", user_consent_url);
+        let code = tokio::select! {
+            callback = tokio::time::timeout(timeout, callback_rx) => match callback {
+                Ok(Ok(v)) => v?,
+                Ok(Err(_)) => return Err(LoginError::Failed("Login server stopped".to_string())),
+                Err(_) => return Err(LoginError::TimedOut),
+            },
+            _ = cancel => return Err(LoginError::Cancelled),
+        };
 
-    webbrowser::open(&user_consent_url).unwrap();
+        let access_token = exchange_code(&client, &provider, &redirect, &state, &code, &verifier).await?;
 
+        let email = account_email(&access_token).await;
+        let expires_at = expires_at(&access_token);
 
-    // Run the server in the background
-    let server_handle = tokio::spawn(server);
+        Ok(GDStruct::new(access_token, email, expires_at, provider.clone()))
+    }.await;
 
-    // Wait for the server to start
-    tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+    let _ = shutdown_tx.send(());
+    result
+}
 
-    let mut end = false;
-    while !end {
-        let val = token_struct.lock().unwrap();
-        if val.state.is_some() {
-            end = true;
+// The page the browser is sent to for the user to allow access to Drive
+async fn consent_url(client: &reqwest::Client, provider: &AuthProvider, redirect: &str, state: &str, challenge: &str) -> Result<String, LoginError> {
+    match provider {
+        AuthProvider::Broker { url } => client.get(format!("{}/auth", url.trim_end_matches('/')))
+            .query(&[("redirect", redirect), ("status", state), ("code_challenge", challenge), ("code_challenge_method", "S256")])
+            .send().await.map_err(failed)?
+            .error_for_status().map_err(failed)?
+            .text().await.map_err(failed),
+        AuthProvider::Google { client_id, .. } => {
+            let query = form_urlencoded::Serializer::new(String::new())
+                .append_pair("client_id", client_id)
+                .append_pair("redirect_uri", redirect)
+                .append_pair("response_type", "code")
+                .append_pair("scope", DRIVE_SCOPE)
+                // Without these Google only gives out a refresh token the first time the app is allowed
+                .append_pair("access_type", "offline")
+                .append_pair("prompt", "consent")
+                .append_pair("state", state)
+                .append_pair("code_challenge", challenge)
+                .append_pair("code_challenge_method", "S256")
+                .finish();
+            Ok(format!("{}?{}", GOOGLE_CONSENT_URL, query))
         }
-        std::mem::drop(val);
-        thread::sleep(Duration::from_secs(1));
+        AuthProvider::ServiceAccount { .. } => Err(LoginError::Failed("Service accounts don't log in through the browser".to_string())),
     }
+}
 
-    // Return the token struct
-    let val = token_struct.lock().unwrap();
-
-    let access_token = client.get("https://entangleauth.eeshwar-krishnan.workers.dev/confirm")
-        .query(&[("redirect", &format!("http://{}", actual_addr)), ("status", &val.state.clone().unwrap()), ("code", &val.code.clone().unwrap())]).send().await.unwrap().json::<AccessToken>().await.unwrap();
-
-    let mut google_drive = Client::new("", 
-    "", format!("http://{}", actual_addr), &access_token.access_token, &access_token.refresh_token); 
+// Trade the authorization code from the redirect for tokens
+async fn exchange_code(client: &reqwest::Client, provider: &AuthProvider, redirect: &str, state: &str, code: &str, verifier: &str) -> Result<AccessToken, LoginError> {
+    let request = match provider {
+        AuthProvider::Broker { url } => client.get(format!("{}/confirm", url.trim_end_matches('/')))
+            .query(&[("redirect", redirect), ("status", state), ("code", code), ("code_verifier", verifier)]),
+        AuthProvider::Google { client_id, client_secret } => client.post(GOOGLE_TOKEN_URL)
+            .form(&[("grant_type", "authorization_code"), ("code", code), ("redirect_uri", redirect), ("client_id", client_id), ("client_secret", client_secret), ("code_verifier", verifier)]),
+        AuthProvider::ServiceAccount { .. } => return Err(LoginError::Failed("Service accounts don't log in through the browser".to_string())),
+    };
 
-    return GDStruct {
-        token: access_token,
-        drive: google_drive,
-    }
-}
\ No newline at end of file
+    request.send().await.map_err(failed)?
+        .error_for_status().map_err(failed)?
+        .json::<AccessToken>().await.map_err(failed)
+}
@@ -0,0 +1,67 @@
+use std::{fs::{self, File}, io::{Error, Write}, path::PathBuf};
+
+use serde::{Serialize, Deserialize};
+
+use crate::error::EntangleError;
+
+// The auth worker Entangle ships with. It holds the OAuth client secret, so it does the token exchanges with Google
+pub(crate) const DEFAULT_BROKER_URL: &str = "https://entangleauth.eeshwar-krishnan.workers.dev";
+
+// Where Google Drive tokens come from, chosen in Settings
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub(crate) enum AuthProvider {
+    // An OAuth broker with the same /auth and /confirm endpoints as the default worker. It has no refresh endpoint, so
+    // a session that runs out goes through the login again
+    Broker { url: String },
+    // Your own Google Cloud OAuth client, of the Desktop app type. Google doesn't treat a desktop client's secret as
+    // confidential, so it's kept in the config file like the rest
+    Google { client_id: String, client_secret: String },
+    // A service account key file, logs in without a browser. subject is a Workspace user to act as through
+    // domain-wide delegation, otherwise the service account only sees what has been shared with it
+    ServiceAccount { key_path: String, subject: Option<String> },
+}
+
+impl Default for AuthProvider {
+    fn default() -> Self {
+        AuthProvider::Broker { url: DEFAULT_BROKER_URL.to_string() }
+    }
+}
+
+fn config_path() -> Result<PathBuf, EntangleError> {
+    dirs::config_dir()
+        .map(|v| v.join("Entangle").join("auth.json"))
+        .ok_or_else(|| EntangleError::Io("Couldn't find the config folder".to_string()))
+}
+
+// The provider picked in Settings, or the default worker if nothing was picked yet
+pub(crate) fn load_provider() -> AuthProvider {
+    let pth = match config_path() {
+        Ok(v) => v,
+        Err(e) => {
+            eprintln!("{}", e);
+            return AuthProvider::default();
+        }
+    };
+    if !pth.exists() {
+        return AuthProvider::default();
+    }
+
+    match fs::read(&pth).map_err(|e| e.to_string()).and_then(|v| serde_json::from_slice(&v).map_err(|e| e.to_string())) {
+        Ok(v) => v,
+        Err(e) => {
+            eprintln!("Failed to read {}: {}", pth.display(), e);
+            AuthProvider::default()
+        }
+    }
+}
+
+pub(crate) fn save_provider(provider: &AuthProvider) -> Result<(), Error> {
+    let pth = config_path()?;
+    if let Some(parent) = pth.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut file = File::create(pth)?;
+    file.write_all(serde_json::to_string_pretty(provider)?.as_bytes())?;
+    Ok(())
+}
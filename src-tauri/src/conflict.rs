@@ -0,0 +1,37 @@
+use std::path::Path;
+
+use serde::{Serialize, Deserialize};
+
+// A file that changed both locally and on the remote since the last sync. The pull leaves the local file alone and
+// puts the remote version next to it, until the user picks which one to keep
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct Conflict {
+    pub path: String,
+    pub copy: String,
+    pub local_sha256: String,
+    pub remote_sha256: String,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Keep {
+    Local,
+    Remote,
+    Both,
+}
+
+// part.sldprt -> part.remote.sldprt
+pub(crate) fn conflict_copy_path(path: &str) -> String {
+    let path = Path::new(path);
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+
+    let name = match path.extension() {
+        Some(ext) => format!("{}.remote.{}", stem, ext.to_string_lossy()),
+        None => format!("{}.remote", stem),
+    };
+
+    match path.parent() {
+        Some(parent) => parent.join(name).to_string_lossy().into_owned(),
+        None => name,
+    }
+}
@@ -0,0 +1,67 @@
+use google_drive::AccessToken;
+use keyring::Entry;
+use serde::{Serialize, Deserialize};
+
+use crate::auth::GDStruct;
+use crate::authconfig::AuthProvider;
+
+// Where the Google Drive login is kept in the OS keyring (Keychain, Credential Manager, Secret Service)
+const KEYRING_SERVICE: &str = "Entangle";
+const KEYRING_USER: &str = "google-drive";
+
+#[derive(Serialize, Deserialize)]
+struct StoredSession {
+    token: AccessToken,
+    email: Option<String>,
+    expires_at: u64,
+    // Logins saved before the provider could be changed all came from the default worker
+    #[serde(default)]
+    provider: AuthProvider,
+}
+
+fn entry() -> Result<Entry, keyring::Error> {
+    Entry::new(KEYRING_SERVICE, KEYRING_USER)
+}
+
+// Remember the Drive login, so the next launch doesn't have to go through the browser again
+pub(crate) fn save_session(gds: &GDStruct) {
+    let session = StoredSession {
+        token: gds.token.clone(),
+        email: gds.email.clone(),
+        expires_at: gds.expires_at,
+        provider: gds.provider.clone(),
+    };
+
+    let result = entry().and_then(|e| e.set_password(&serde_json::to_string(&session).unwrap()));
+    if let Err(e) = result {
+        eprintln!("Failed to save Google Drive login: {}", e);
+    }
+}
+
+// The Drive login from the last launch, if there was one. An expired access token is fine, it gets refreshed before
+// it is used
+pub(crate) fn load_session() -> Option<GDStruct> {
+    let stored = match entry().and_then(|e| e.get_password()) {
+        Ok(v) => v,
+        Err(keyring::Error::NoEntry) => return None,
+        Err(e) => {
+            eprintln!("Failed to load Google Drive login: {}", e);
+            return None;
+        }
+    };
+
+    match serde_json::from_str::<StoredSession>(&stored) {
+        Ok(session) => Some(GDStruct::new(session.token, session.email, session.expires_at, session.provider)),
+        Err(e) => {
+            eprintln!("Failed to read saved Google Drive login: {}", e);
+            None
+        }
+    }
+}
+
+pub(crate) fn clear_session() {
+    match entry().and_then(|e| e.delete_password()) {
+        Ok(_) | Err(keyring::Error::NoEntry) => {},
+        Err(e) => eprintln!("Failed to remove Google Drive login: {}", e),
+    }
+}
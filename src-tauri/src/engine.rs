@@ -0,0 +1,1045 @@
+use std::{path::Path, fs, io::{Error, ErrorKind}, collections::HashSet};
+
+use futures_util::stream::{self, StreamExt};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+use crate::{FileData, SyncFile, SyncInfo, OnHashProgress, hash_local_files, list_local_folders, read_sync_file, write_atomic, write_sync_file};
+use crate::ignorefile::IgnoreRules;
+use crate::conflict::{Conflict, Keep, conflict_copy_path};
+use crate::history::{Author, CheckoutPlan, History, Revision, plan_checkout};
+use crate::lock::{Lock, set_readonly};
+use crate::objects::Stash;
+use crate::error::EntangleError;
+use crate::remote::Remote;
+use crate::status::{FileStatus, file_status, folder_statuses};
+
+// How many files are downloaded at once during a pull
+const PULL_CONCURRENCY: usize = 10;
+
+fn sync_file_path(project: &Path, projectname: &str) -> std::path::PathBuf {
+    project.join(format!("{}.sync", projectname))
+}
+
+fn upsert_file(files: &mut Vec<SyncFile>, file: SyncFile) {
+    match files.iter_mut().find(|f| f.path == file.path) {
+        Some(f) => *f = file,
+        None => files.push(file),
+    }
+}
+
+fn upsert_folder(folders: &mut Vec<String>, folder: &str) {
+    if !folders.iter().any(|f| f == folder) {
+        folders.push(folder.to_string());
+    }
+}
+
+// Every folder a file sits in, so the manifest's folder list stays complete
+fn parent_folders(path: &str) -> Vec<String> {
+    Path::new(path)
+        .ancestors()
+        .skip(1)
+        .map(|p| p.to_string_lossy().into_owned())
+        .collect()
+}
+
+fn write_local(project: &Path, stash: &Stash, path: &str, data: &[u8]) -> Result<(), Error> {
+    let filepath = project.join(path);
+
+    // Local work is stashed in the project's own object store before it is thrown away
+    stash.preserve(&filepath)?;
+
+    // Read-only files are only kept that way as a reminder to lock them first, they still get updated
+    let readonly = filepath.exists() && fs::metadata(&filepath)?.permissions().readonly();
+    if readonly {
+        set_readonly(&filepath, false)?;
+    }
+
+    if let Some(parent) = filepath.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    // An interrupted write leaves the old version in place rather than half of the new one
+    write_atomic(&filepath, data)?;
+
+    if readonly {
+        set_readonly(&filepath, true)?;
+    }
+    Ok(())
+}
+
+// Everything downloaded is checked against the sha256 it was committed with before it goes anywhere near the project
+fn check_download(path: &str, data: &[u8], sha256: &str) -> Result<(), Error> {
+    let actual = format!("{:x}", Sha256::digest(data));
+    if actual != sha256 {
+        return Err(EntangleError::Manifest(format!("The copy of {} on the remote doesn't match the version that was committed: expected sha256 {}, got {}", path, sha256, actual)).into());
+    }
+    Ok(())
+}
+
+fn remove_local(project: &Path, stash: &Stash, path: &str) -> Result<(), Error> {
+    let filepath = project.join(path);
+    stash.preserve(&filepath)?;
+    // Windows won't delete read-only files
+    set_readonly(&filepath, false)?;
+    fs::remove_file(filepath)
+}
+
+fn locked_error(lock: &Lock) -> Error {
+    EntangleError::Locked(format!("{} is locked by {} ({}) on {}", lock.path, lock.user, lock.email, lock.machine)).into()
+}
+
+fn remote_moved_error(reason: &str) -> Error {
+    EntangleError::Conflict(format!("{}. The remote has moved on, pull first and commit again", reason)).into()
+}
+
+// What status found, along with the remote revision it compared against. Commits are checked against that revision,
+// so nothing the files were picked without knowing about gets overwritten
+#[derive(Debug, Serialize)]
+pub(crate) struct Status {
+    pub files: Vec<FileData>,
+    pub revision: Option<String>,
+}
+
+// Compare the local folder against the last synced baseline and the remote manifest
+pub(crate) async fn status(remote: &dyn Remote, project: &Path, projectname: &str, on_progress: &OnHashProgress) -> Result<Status, Error> {
+    let rules = IgnoreRules::load(project, projectname);
+    let local_files = hash_local_files(project, &rules, on_progress)?;
+    let local_paths: HashSet<&String> = local_files.iter().map(|f| &f.path).collect();
+    let local_folders = list_local_folders(project, &rules);
+
+    let local_sync_info: SyncInfo = read_sync_file(sync_file_path(project, projectname))?;
+    let remote_sync_info: SyncInfo = remote.read_manifest().await?;
+    let locks = remote.read_locks().await?;
+
+    // Ignored paths are left out on every side, so files committed before they were ignored don't show up either
+    let project_folders: Vec<String> = local_sync_info.folders.iter().filter(|f| !rules.is_ignored(Path::new(f), true)).cloned().collect();
+    let remote_folders: Vec<String> = remote_sync_info.folders.iter().filter(|f| !rules.is_ignored(Path::new(f), true)).cloned().collect();
+
+    // Match the file names between the local, remote, and project files
+    let mut result: Vec<FileData> = local_files
+        .iter()
+        .map(|local_file| {
+            let remote_file = remote_sync_info.files.iter().find(|rf: &&SyncFile| rf.path == local_file.path);
+            let project_file = local_sync_info.files.iter().find(|rf: &&SyncFile| rf.path == local_file.path);
+
+            FileData {
+                name: local_file.name.clone(),
+                path: local_file.path.clone(),
+                select: true, // Assuming all files are selected by default
+                status: file_status(Some(local_file), project_file, remote_file),
+                lock: None,
+                modified_by: remote_file.or(project_file).and_then(|f| f.modified_by.clone()),
+            }
+        })
+        .collect();
+
+    // Include files from remote that are not in local
+    result.extend(
+        remote_sync_info
+            .files
+            .iter()
+            .filter(|rf| !local_paths.contains(&rf.path))
+            .filter(|rf| !rules.is_ignored(Path::new(&rf.path), false))
+            .map(|rf| {
+                let project_file = local_sync_info.files.iter().find(|rf2: &&SyncFile| rf2.path == rf.path);
+
+                FileData {
+                    name: rf.path.clone(),
+                    path: rf.path.clone(),
+                    select: true, // Assuming all files from remote are selected by default
+                    status: file_status(None, project_file, Some(rf)),
+                    lock: None,
+                    modified_by: rf.modified_by.clone(),
+                }
+            }),
+    );
+
+    result.extend(folder_statuses(&local_folders, &project_folders, &remote_folders));
+
+    // Files with an open conflict stay flagged until resolve_conflict is called, and the remote copy kept next to
+    // them is not something to commit
+    for fd in result.iter_mut() {
+        if local_sync_info.conflicts.iter().any(|c| c.path == fd.path) {
+            fd.status = FileStatus::Conflict;
+        }
+        fd.lock = locks.get(&fd.path).cloned();
+    }
+    result.retain(|fd| !local_sync_info.conflicts.iter().any(|c| c.copy == fd.path));
+
+    Ok(Status { files: result, revision: remote_sync_info.revision })
+}
+
+// What committing the selected files would send to the remote
+#[derive(Debug, Default, Serialize)]
+pub(crate) struct CommitPlan {
+    pub folders: Vec<String>,
+    pub upload: Vec<SyncFile>,
+    // Changed since the last sync, but the remote manifest already has the same contents at the same path
+    pub unchanged: Vec<SyncFile>,
+    pub delete: Vec<String>,
+    // What the uploads add up to. Only contents the remote has never had are sent, the browsable copies are made on
+    // the remote from the object store
+    pub bytes: u64,
+    // Files someone put on the remote by hand, outside any commit, that the browsable copies would replace
+    pub replace_uncommitted: Vec<String>,
+}
+
+fn plan_files(project: &Path, projectname: &str, files: &[FileData], manifest: &SyncInfo, on_progress: &OnHashProgress) -> Result<CommitPlan, Error> {
+    let rules = IgnoreRules::load(project, projectname);
+    let local_files = hash_local_files(project, &rules, on_progress)?;
+    let mut plan = CommitPlan::default();
+
+    for f in files.iter().filter(|f| f.select && f.status.is_local_change()) {
+        let local_path = project.join(&f.path);
+
+        if rules.is_ignored(Path::new(&f.path), local_path.is_dir()) {
+            continue;
+        }
+
+        if local_path.is_dir() {
+            plan.folders.push(f.path.clone());
+        }else if let Some(lf) = local_files.iter().find(|lf| lf.path == f.path) {
+            if manifest.files.iter().any(|mf| mf.path == lf.path && mf.sha256 == lf.sha256) {
+                plan.unchanged.push(lf.clone());
+                continue;
+            }
+
+            // Contents some file in the manifest already has are in the object store
+            if !manifest.files.iter().any(|mf| mf.sha256 == lf.sha256) {
+                plan.bytes += fs::metadata(&local_path)?.len();
+            }
+            plan.upload.push(lf.clone());
+        }else{
+            plan.delete.push(f.path.clone());
+        }
+    }
+
+    Ok(plan)
+}
+
+// What a commit that went in reports back. The warnings are for what went wrong once it was in, like the browsable
+// copies on the remote not being updated, which doesn't undo the commit
+#[derive(Debug, Serialize)]
+pub(crate) struct CommitOutcome {
+    pub revision: Revision,
+    pub warnings: Vec<String>,
+}
+
+// Work out what committing the selected files would transfer, without touching the remote. The remote is walked as
+// well, so the plan can point out files the commit would replace that never went through one
+pub(crate) async fn plan_commit(remote: &dyn Remote, project: &Path, projectname: &str, files: &[FileData], on_progress: &OnHashProgress) -> Result<CommitPlan, Error> {
+    let manifest = remote.read_manifest().await?;
+    let mut plan = plan_files(project, projectname, files, &manifest, on_progress)?;
+
+    let on_remote: HashSet<String> = remote.list().await?.into_iter().collect();
+    plan.replace_uncommitted = plan.upload.iter()
+        .filter(|lf| on_remote.contains(&lf.path) && !manifest.files.iter().any(|mf| mf.path == lf.path))
+        .map(|lf| lf.path.clone())
+        .collect();
+    Ok(plan)
+}
+
+// Push the selected local changes to the remote and record them as a new revision. revision is the remote revision
+// status gave the files' statuses against, the commit is refused if the remote has moved past it. Files locked by
+// someone else are refused unless force is set. Up to concurrency files are uploaded at once. New versions are staged
+// in the object store and checked there before the manifest is swapped, so a push cut off halfway never shows up as a
+// new revision
+pub(crate) async fn commit(remote: &dyn Remote, project: &Path, projectname: &str, files: &[FileData], revision: Option<String>, msg: String, author: Author, force: bool, concurrency: usize, on_progress: &OnHashProgress) -> Result<CommitOutcome, Error> {
+    // Files changed on both sides have to go through resolve_conflict first, otherwise one side's work is lost
+    if let Some(f) = files.iter().find(|f| f.select && f.status == FileStatus::Conflict) {
+        return Err(EntangleError::Conflict(format!("Refusing to overwrite conflicting file: {}", f.path)).into());
+    }
+
+    let author = Author { account: remote.account(), ..author };
+
+    if !force {
+        let locks = remote.read_locks().await?;
+        let locked = files.iter()
+            .filter(|f| f.select && f.status.is_local_change())
+            .find_map(|f| locks.get(&f.path).filter(|l| !l.held_by(&author.email)));
+        if let Some(l) = locked {
+            return Err(locked_error(l));
+        }
+    }
+
+    let mut baseline = read_sync_file(sync_file_path(project, projectname))?;
+    let mut manifest = remote.read_manifest().await?;
+
+    // The statuses the files were picked from were worked out against the remote as it was back then. Anything
+    // committed since could be overwritten without anyone noticing
+    if manifest.revision != revision {
+        return Err(remote_moved_error("Someone else has committed since the files were listed"));
+    }
+    let plan = plan_files(project, projectname, files, &manifest, on_progress)?;
+
+    // A file the remote has a different version of than the last pull, or has dropped since, isn't ours to overwrite
+    let moved = plan.upload.iter().map(|lf| &lf.path).chain(plan.delete.iter())
+        .find(|path| {
+            let remote_sha = manifest.files.iter().find(|mf| mf.path == **path).map(|mf| &mf.sha256);
+            let baseline_sha = baseline.files.iter().find(|bf| bf.path == **path).map(|bf| &bf.sha256);
+            remote_sha != baseline_sha
+        });
+    if let Some(path) = moved {
+        return Err(remote_moved_error(&format!("{} has changed on the remote since your last pull", path)));
+    }
+
+    // Stage: every new version goes into the object store and is checked there. Objects are only ever reached through
+    // a manifest, so until the manifest is swapped below nobody sees any of this
+    let staged: Vec<Result<(), Error>> = stream::iter(plan.upload.clone())
+        .map(|lf| async move {
+            let local_path = project.join(&lf.path);
+            remote.put_object(&lf.sha256, &lf.path, &local_path).await?;
+
+            let size = fs::metadata(&local_path)?.len();
+            if !remote.verify_object(&lf.sha256, size).await? {
+                return Err(EntangleError::Io(format!("{} did not arrive on the remote intact", lf.path)).into());
+            }
+            Ok(())
+        })
+        .buffer_unordered(concurrency.max(1))
+        .collect()
+        .await;
+
+    for result in staged {
+        result?;
+    }
+
+    for folder in &plan.folders {
+        upsert_folder(&mut manifest.folders, folder);
+        upsert_folder(&mut baseline.folders, folder);
+    }
+
+    for lf in &plan.upload {
+        for folder in parent_folders(&lf.path) {
+            upsert_folder(&mut manifest.folders, &folder);
+            upsert_folder(&mut baseline.folders, &folder);
+        }
+        let file = SyncFile { modified_by: Some(author.display_name()), ..lf.clone() };
+        upsert_file(&mut manifest.files, file.clone());
+        upsert_file(&mut baseline.files, file);
+    }
+
+    // Already on the remote as they are, only the local baseline was behind
+    for lf in &plan.unchanged {
+        for folder in parent_folders(&lf.path) {
+            upsert_folder(&mut baseline.folders, &folder);
+        }
+        if let Some(mf) = manifest.files.iter().find(|mf| mf.path == lf.path) {
+            upsert_file(&mut baseline.files, mf.clone());
+        }
+    }
+
+    for path in &plan.delete {
+        manifest.files.retain(|mf| mf.path != *path);
+        manifest.folders.retain(|mf| mf != path);
+        baseline.files.retain(|bf| bf.path != *path);
+        baseline.folders.retain(|bf| bf != path);
+    }
+
+    let parent = head_revision(remote, &manifest, &mut None).await?;
+    let new_revision = Revision::new(parent, &author, msg.clone(), manifest.files.clone());
+    manifest.msg = msg.clone();
+    manifest.author = author.display_name();
+    manifest.revision = Some(new_revision.id.clone());
+    manifest.conflicts = Vec::new();
+
+    // The revision is stored first, a manifest naming a revision the remote doesn't have would break checking it out
+    // and the parent of the next commit. A revision the manifest never got to is harmless
+    remote.write_revision(&new_revision).await?;
+    swap_manifest(remote, &manifest, &revision).await?;
+
+    baseline.msg = msg;
+    baseline.author = author.display_name();
+    baseline.revision = Some(new_revision.id.clone());
+    write_sync_file(&sync_file_path(project, projectname), &serde_json::to_string_pretty(&baseline).unwrap())?;
+
+    // The commit has gone in by now, failing past this point would only make it look like it has to be done again
+    let mut warnings = Vec::new();
+    if let Err(e) = update_files(remote, &plan, concurrency).await {
+        eprintln!("Committed {}, but updating the browsable files on the remote failed: {}", new_revision.id, e);
+        warnings.push(format!("The commit went in, but updating the browsable files on the remote failed: {}", e));
+    }
+
+    Ok(CommitOutcome { revision: new_revision, warnings })
+}
+
+// Write the new manifest, unless someone else's commit has replaced the one at expected in the meantime. This is the
+// point a commit happens. Remotes have no way to replace a file only if it's unchanged, so the check is made right
+// before the write, with nothing in between
+async fn swap_manifest(remote: &dyn Remote, manifest: &SyncInfo, expected: &Option<String>) -> Result<(), Error> {
+    if remote.read_manifest().await?.revision != *expected {
+        return Err(remote_moved_error("Someone else committed while this commit was uploading"));
+    }
+    remote.write_manifest(manifest).await
+}
+
+// Bring the browsable copy of the project on the remote in line with a commit that has gone in. Pulls read from the
+// object store, so they don't depend on this having finished
+async fn update_files(remote: &dyn Remote, plan: &CommitPlan, concurrency: usize) -> Result<(), Error> {
+    // Folders go first, parents before children, so uploads running side by side never race to create the same one
+    let mut folders: Vec<String> = plan.folders.iter().cloned()
+        .chain(plan.upload.iter().flat_map(|lf| parent_folders(&lf.path)))
+        .filter(|f| !f.is_empty())
+        .collect();
+    folders.sort_by(|a, b| Path::new(a).components().count().cmp(&Path::new(b).components().count()).then(a.cmp(b)));
+    folders.dedup();
+
+    for folder in &folders {
+        remote.create_folder(folder).await?;
+    }
+
+    let copied: Vec<Result<(), Error>> = stream::iter(plan.upload.clone())
+        .map(|lf| async move { remote.copy_object(&lf.sha256, &lf.path).await })
+        .buffer_unordered(concurrency.max(1))
+        .collect()
+        .await;
+
+    for result in copied {
+        result?;
+    }
+
+    for path in &plan.delete {
+        // Gone locally, so it goes from the remote too
+        remote.delete(path).await?;
+    }
+
+    Ok(())
+}
+
+// Bring the selected remote changes into the local folder
+pub(crate) async fn pull(remote: &dyn Remote, project: &Path, projectname: &str, files: &[FileData]) -> Result<(), Error> {
+    let rules = IgnoreRules::load(project, projectname);
+    let mut baseline = read_sync_file(sync_file_path(project, projectname))?;
+    let manifest = remote.read_manifest().await?;
+    let stash = Stash::new(project, baseline.files.iter().chain(manifest.files.iter()).map(|f| f.sha256.clone()));
+
+    let mut downloads: Vec<(SyncFile, String)> = Vec::new();
+    let mut empty_folders: Vec<String> = Vec::new();
+
+    for f in files.iter().filter(|f| f.select) {
+        let local_path = project.join(&f.path);
+
+        if rules.is_ignored(Path::new(&f.path), manifest.folders.contains(&f.path) || local_path.is_dir()) {
+            continue;
+        }
+
+        match f.status {
+            // Not on the remote (anymore), so it goes locally too. Only the files picked go, each stashed first. A folder
+            // goes once whatever was picked out of it is gone, and only if nothing else is left in it
+            FileStatus::LocalAdded | FileStatus::Untracked => {
+                if local_path.is_dir() {
+                    if !f.path.is_empty() {
+                        empty_folders.push(f.path.clone());
+                    }
+                }else if local_path.exists() {
+                    remove_local(project, &stash, &f.path)?;
+                    baseline.files.retain(|bf| bf.path != f.path);
+                }
+            }
+            _ if manifest.folders.contains(&f.path) => {
+                fs::create_dir_all(&local_path)?;
+                upsert_folder(&mut baseline.folders, &f.path);
+            }
+            // Both sides changed this file, so the remote version goes next to the local one instead of over it
+            FileStatus::Conflict => {
+                let rf = match manifest.files.iter().find(|rf| rf.path == f.path) {
+                    Some(v) => v,
+                    None => continue,
+                };
+                let copy = conflict_copy_path(&f.path);
+
+                baseline.conflicts.retain(|c| c.path != f.path);
+                baseline.conflicts.push(Conflict {
+                    path: f.path.clone(),
+                    copy: copy.clone(),
+                    local_sha256: crate::compute_sha256(&local_path)?,
+                    remote_sha256: rf.sha256.clone(),
+                });
+                downloads.push((rf.clone(), copy));
+            }
+            _ => {
+                if let Some(rf) = manifest.files.iter().find(|rf| rf.path == f.path) {
+                    downloads.push((rf.clone(), rf.path.clone()));
+                }
+            }
+        }
+    }
+
+    // Deepest first, so a folder whose only content was an empty folder goes too
+    empty_folders.sort_by_key(|folder| std::cmp::Reverse(Path::new(folder).components().count()));
+    for folder in empty_folders {
+        let local_path = project.join(&folder);
+        if fs::read_dir(&local_path)?.next().is_none() {
+            fs::remove_dir(&local_path)?;
+            baseline.folders.retain(|bf| *bf != folder);
+        }
+    }
+
+    let stash = &stash;
+    let results: Vec<Result<(SyncFile, String), (String, Error)>> = stream::iter(downloads)
+        .map(|(rf, target)| async move {
+            let download = async {
+                // The object store has exactly what the manifest names, the copy at rf.path may be mid-update
+                let data = match remote.get_object(&rf.sha256).await {
+                    Ok(v) => v,
+                    // Committed before the remote had an object store
+                    Err(e) if e.kind() == ErrorKind::NotFound => remote.get_blob(&rf.path).await?,
+                    Err(e) => return Err(e),
+                };
+                check_download(&rf.path, &data, &rf.sha256)?;
+                write_local(project, stash, &target, &data)
+            };
+            match download.await {
+                Ok(()) => Ok((rf, target)),
+                Err(e) => Err((rf.path, e)),
+            }
+        })
+        .buffer_unordered(PULL_CONCURRENCY)
+        .collect()
+        .await;
+
+    // Everything that did arrive is recorded, even if some of it didn't, otherwise it shows up as changed on both sides
+    let mut failed: Vec<(String, EntangleError)> = Vec::new();
+    for result in results {
+        let (rf, target) = match result {
+            Ok(v) => v,
+            Err((path, e)) => {
+                failed.push((path, e.into()));
+                continue;
+            }
+        };
+
+        // Conflict copies don't move the baseline, the file only counts as synced once the conflict is resolved
+        if rf.path == target {
+            for folder in parent_folders(&rf.path) {
+                upsert_folder(&mut baseline.folders, &folder);
+            }
+            upsert_file(&mut baseline.files, rf);
+        }
+    }
+
+    // Only a complete pull brings the baseline up to the remote revision
+    if failed.is_empty() {
+        baseline.revision = manifest.revision.clone();
+    }
+    write_sync_file(&sync_file_path(project, projectname), &serde_json::to_string_pretty(&baseline).unwrap())?;
+
+    if failed.is_empty() {
+        return Ok(());
+    }
+    let message = format!(
+        "{} file(s) could not be pulled, the rest were:\n{}",
+        failed.len(),
+        failed.iter().map(|(path, e)| format!("{}: {}", path, e)).collect::<Vec<_>>().join("\n")
+    );
+    // A lapsed login or a download that doesn't match the manifest says the most about what to do next
+    let worst = failed.iter()
+        .find(|(_, e)| matches!(e, EntangleError::NotLoggedIn | EntangleError::Manifest(_)))
+        .unwrap_or(&failed[0]);
+    Err(worst.1.with_message(message).into())
+}
+
+// Settle an open conflict. The baseline for the file moves to the remote version either way, so keeping the local
+// file leaves it as a local change to commit, while keeping the remote one makes it clean again. Only the local .sync
+// changes, the remote manifest already has the remote version. Whatever is kept locally reaches the remote with the
+// next commit, like any other change
+pub(crate) fn resolve_conflict(project: &Path, projectname: &str, path: &str, keep: Keep) -> Result<(), Error> {
+    let sync_file_path = sync_file_path(project, projectname);
+    let mut sync_info = read_sync_file(sync_file_path.clone())?;
+
+    let conflict = match sync_info.conflicts.iter().position(|c| c.path == path) {
+        Some(i) => sync_info.conflicts.remove(i),
+        None => return Err(EntangleError::NotFound(format!("No open conflict for {}", path)).into()),
+    };
+
+    let filepath = project.join(&conflict.path);
+    let copypath = project.join(&conflict.copy);
+
+    match keep {
+        Keep::Local => match fs::remove_file(&copypath) {
+            // Already cleared away by hand
+            Err(e) if e.kind() != ErrorKind::NotFound => return Err(e),
+            _ => {}
+        },
+        Keep::Remote => {
+            Stash::new(project, sync_info.files.iter().map(|f| f.sha256.clone())).preserve(&filepath)?;
+            fs::rename(&copypath, &filepath)?;
+        }
+        // The remote copy simply stays around as a new file
+        Keep::Both => {}
+    }
+
+    match sync_info.files.iter_mut().find(|f| f.path == conflict.path) {
+        Some(f) => f.sha256 = conflict.remote_sha256.clone(),
+        None => sync_info.files.push(SyncFile {
+            name: filepath.file_name().map(|v| v.to_string_lossy().into_owned()).unwrap_or_default(),
+            path: conflict.path.clone(),
+            sha256: conflict.remote_sha256.clone(),
+            modified_by: None,
+        }),
+    }
+
+    write_sync_file(&sync_file_path, &serde_json::to_string_pretty(&sync_info).unwrap())
+}
+
+// The revision the manifest is at. Manifests written before they named their revision are at the last one in the
+// legacy history
+async fn head_revision(remote: &dyn Remote, manifest: &SyncInfo, legacy: &mut Option<History>) -> Result<Option<String>, Error> {
+    if manifest.revision.is_some() {
+        return Ok(manifest.revision.clone());
+    }
+    Ok(legacy_history(remote, legacy).await?.head().map(|rev| rev.id.clone()))
+}
+
+// The legacy history file, read the first time it is needed
+async fn legacy_history<'a>(remote: &dyn Remote, legacy: &'a mut Option<History>) -> Result<&'a History, Error> {
+    if legacy.is_none() {
+        *legacy = Some(remote.read_history().await?);
+    }
+    Ok(legacy.as_ref().unwrap())
+}
+
+// A revision from its own file, or from the legacy history for one committed before revisions got their own files
+async fn get_revision(remote: &dyn Remote, id: &str, legacy: &mut Option<History>) -> Result<Revision, Error> {
+    match remote.read_revision(id).await {
+        Err(e) if e.kind() == ErrorKind::NotFound => {}
+        result => return result,
+    }
+    match legacy_history(remote, legacy).await?.get(id) {
+        Some(v) => Ok(v.clone()),
+        None => Err(EntangleError::NotFound(format!("Unknown revision: {}", id)).into()),
+    }
+}
+
+// Every revision the remote is made of, walked back through the parent links from the manifest, newest first
+pub(crate) async fn list_history(remote: &dyn Remote) -> Result<Vec<Revision>, Error> {
+    let manifest = remote.read_manifest().await?;
+    let mut legacy = None;
+    let mut result = Vec::new();
+
+    let mut current = head_revision(remote, &manifest, &mut legacy).await?;
+    while let Some(id) = current {
+        let rev = get_revision(remote, &id, &mut legacy).await?;
+        current = rev.parent.clone();
+        result.push(rev);
+    }
+    Ok(result)
+}
+
+// Roll the local project back to the files of a past revision. With apply set to false nothing is touched and the
+// plan only reports what would change. The local .sync is left alone, so the rolled back files show up as local
+// changes that can be committed on top of the current remote. Files that were never committed are left alone.
+pub(crate) async fn checkout(remote: &dyn Remote, project: &Path, projectname: &str, revision: &str, apply: bool, on_progress: &OnHashProgress) -> Result<CheckoutPlan, Error> {
+    let rev = get_revision(remote, revision, &mut None).await?;
+    let baseline = read_sync_file(sync_file_path(project, projectname))?;
+    let manifest = remote.read_manifest().await?;
+
+    let local_files = hash_local_files(project, &IgnoreRules::load(project, projectname), on_progress)?;
+    let plan = plan_checkout(&rev, &local_files, &baseline, &manifest);
+
+    if !apply {
+        return Ok(plan);
+    }
+    let stash = Stash::new(project, rev.files.iter().chain(manifest.files.iter()).map(|f| f.sha256.clone()));
+
+    for file in plan.overwrite.iter().chain(plan.create.iter()) {
+        let data = remote.get_object(&file.sha256).await?;
+        check_download(&file.path, &data, &file.sha256)?;
+        write_local(project, &stash, &file.path, &data)?;
+    }
+
+    for path in &plan.delete {
+        remove_local(project, &stash, path)?;
+    }
+
+    Ok(plan)
+}
+
+// Write a version of a file out of the remote object store into the project
+pub(crate) async fn restore(remote: &dyn Remote, project: &Path, path: &str, sha256: &str) -> Result<(), Error> {
+    let data = remote.get_object(sha256).await?;
+    check_download(path, &data, sha256)?;
+    let stash = Stash::new(project, remote.read_manifest().await?.files.into_iter().map(|f| f.sha256));
+    write_local(project, &stash, path, &data)
+}
+
+// Reserve a file on the remote for the current user, and make sure it can be edited locally
+pub(crate) async fn lock(remote: &dyn Remote, project: &Path, path: &str, user: String, email: String) -> Result<Lock, Error> {
+    // Locks are told apart by email, one without it would be anyone's to give up
+    if email.is_empty() {
+        return Err(EntangleError::NotLoggedIn.into());
+    }
+
+    let mut locks = remote.read_locks().await?;
+
+    if let Some(l) = locks.get(path).filter(|l| !l.held_by(&email)) {
+        return Err(locked_error(l));
+    }
+
+    let lock = Lock::new(path.to_string(), user, email);
+    locks.insert(lock.clone());
+    remote.write_locks(&locks).await?;
+
+    // Someone locking at the same moment can write over this, whoever's lock is stored afterwards is the one that holds
+    match remote.read_locks().await?.get(path) {
+        Some(l) if *l == lock => {}
+        Some(l) => return Err(locked_error(l)),
+        None => return Err(EntangleError::Conflict(format!("Someone else changed the locks while {} was being locked, try again", path)).into()),
+    }
+
+    let local_path = project.join(path);
+    if local_path.is_file() {
+        set_readonly(&local_path, false)?;
+    }
+
+    Ok(lock)
+}
+
+// Give up a lock. Someone else's lock can only be broken with force set. With readonly set the local file is made
+// read-only again until it is next locked
+pub(crate) async fn unlock(remote: &dyn Remote, project: &Path, path: &str, email: &str, force: bool, readonly: bool) -> Result<(), Error> {
+    if email.is_empty() {
+        return Err(EntangleError::NotLoggedIn.into());
+    }
+
+    let held = remote.read_locks().await?.get(path).cloned();
+    if let Some(l) = held.as_ref().filter(|l| !force && !l.held_by(email)) {
+        return Err(locked_error(l));
+    }
+
+    // Like swap_manifest, the locks are read again right before the write, so a lock someone took or changed in the
+    // meantime is neither given up nor written over
+    let mut locks = remote.read_locks().await?;
+    if locks.get(path) != held.as_ref() {
+        return Err(EntangleError::Conflict(format!("Someone else changed the lock on {} while it was being given up, try again", path)).into());
+    }
+    locks.remove(path);
+    remote.write_locks(&locks).await?;
+
+    let local_path = project.join(path);
+    if readonly && local_path.is_file() {
+        set_readonly(&local_path, true)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::remote::LocalFolderRemote;
+    use crate::testproject::{PROJECT, TestDir, selected, sha256_of, synced_file};
+
+    fn folder_remote(dir: &TestDir) -> LocalFolderRemote {
+        LocalFolderRemote::new(dir.path.to_str().unwrap(), PROJECT)
+    }
+
+    fn status_of(listed: &Status, path: &str) -> Option<FileStatus> {
+        listed.files.iter().find(|fd| fd.path == path).map(|fd| fd.status)
+    }
+
+    fn read_baseline(project: &TestDir) -> SyncInfo {
+        read_sync_file(sync_file_path(&project.path, PROJECT)).unwrap()
+    }
+
+    #[tokio::test]
+    async fn reverting_new_work_only_removes_what_was_picked() {
+        let remote_dir = TestDir::project(&SyncInfo::default());
+        let project = TestDir::project(&SyncInfo::default());
+        project.write("drafts/keep.sldprt", b"keep");
+        project.write("drafts/scrap.sldprt", b"scrap");
+        project.write("empty/old/.placeholder", b"");
+        fs::remove_file(project.path.join("empty/old/.placeholder")).unwrap();
+        let remote = folder_remote(&remote_dir);
+
+        let rows = [
+            selected("", FileStatus::Untracked),
+            selected("drafts", FileStatus::Untracked),
+            selected("empty", FileStatus::Untracked),
+            selected("empty/old", FileStatus::Untracked),
+            selected("drafts/scrap.sldprt", FileStatus::Untracked),
+        ];
+        pull(&remote, &project.path, PROJECT, &rows).await.unwrap();
+
+        assert_eq!(project.read("drafts/keep.sldprt").unwrap(), b"keep");
+        assert_eq!(project.read("drafts/scrap.sldprt"), None);
+        assert!(!project.path.join("empty").exists());
+        // What was removed can still be got back
+        assert!(project.path.join(crate::objects::OBJECTS_DIR).join(sha256_of(b"scrap")).is_file());
+    }
+
+    #[tokio::test]
+    async fn a_failed_download_does_not_lose_the_ones_that_arrived() {
+        let manifest = SyncInfo {
+            files: vec![synced_file("parts/bracket.sldprt", b"bracket"), synced_file("parts/missing.sldprt", b"missing")],
+            folders: vec!["parts".to_string()],
+            revision: Some("r1".to_string()),
+            ..Default::default()
+        };
+        let remote_dir = TestDir::project(&manifest);
+        remote_dir.write("parts/bracket.sldprt", b"bracket");
+        let project = TestDir::project(&SyncInfo::default());
+        let remote = folder_remote(&remote_dir);
+
+        let rows = [
+            selected("parts/bracket.sldprt", FileStatus::RemoteAdded),
+            selected("parts/missing.sldprt", FileStatus::RemoteAdded),
+        ];
+        let result = pull(&remote, &project.path, PROJECT, &rows).await;
+
+        let error = result.unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::NotFound);
+        assert!(error.to_string().contains("parts/missing.sldprt"));
+        assert_eq!(project.read("parts/bracket.sldprt").unwrap(), b"bracket");
+        let baseline = read_baseline(&project);
+        assert!(baseline.files.iter().any(|f| f.path == "parts/bracket.sldprt"));
+        assert!(!baseline.files.iter().any(|f| f.path == "parts/missing.sldprt"));
+        // Still behind the remote until the rest comes down
+        assert_eq!(baseline.revision, None);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn each_revision_is_stored_on_its_own_on_top_of_the_last() {
+        // A remote committed to back when every revision went into the one .history file
+        let legacy = Revision::new(None, &Author::default(), "Old".to_string(), Vec::new());
+        let remote_dir = TestDir::project(&SyncInfo { revision: Some(legacy.id.clone()), ..Default::default() });
+        remote_dir.write("test.history", &serde_json::to_vec(&History { revisions: vec![legacy.clone()] }).unwrap());
+        let project = TestDir::project(&SyncInfo::default());
+        let remote = folder_remote(&remote_dir);
+
+        project.write("bracket.sldprt", b"bracket");
+        let file = selected("bracket.sldprt", FileStatus::LocalAdded);
+        let first = commit(&remote, &project.path, PROJECT, &[file], Some(legacy.id.clone()), "Add".to_string(), Author::default(), false, 1, &|_| {}).await.unwrap().revision;
+        project.write("bracket.sldprt", b"bracket v2");
+        let file = selected("bracket.sldprt", FileStatus::LocalModified);
+        let second = commit(&remote, &project.path, PROJECT, &[file], Some(first.id.clone()), "Edit".to_string(), Author::default(), false, 1, &|_| {}).await.unwrap().revision;
+
+        let history = list_history(&remote).await.unwrap();
+
+        assert_eq!(history.iter().map(|rev| rev.id.clone()).collect::<Vec<_>>(), vec![second.id.clone(), first.id.clone(), legacy.id]);
+        assert!(remote_dir.path.join(crate::history::revision_path(&second.id)).is_file());
+        assert_eq!(remote.read_history().await.unwrap().revisions.len(), 1);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn checking_out_an_old_revision_keeps_uncommitted_work() {
+        let remote_dir = TestDir::project(&SyncInfo::default());
+        let project = TestDir::project(&SyncInfo::default());
+        let remote = folder_remote(&remote_dir);
+        project.write("bracket.sldprt", b"bracket");
+        let file = selected("bracket.sldprt", FileStatus::LocalAdded);
+        let first = commit(&remote, &project.path, PROJECT, &[file], None, "Add bracket".to_string(), Author::default(), false, 1, &|_| {}).await.unwrap().revision;
+        project.write("plate.sldprt", b"plate");
+        let file = selected("plate.sldprt", FileStatus::LocalAdded);
+        commit(&remote, &project.path, PROJECT, &[file], Some(first.id.clone()), "Add plate".to_string(), Author::default(), false, 1, &|_| {}).await.unwrap();
+        project.write("draft.sldprt", b"draft");
+
+        checkout(&remote, &project.path, PROJECT, &first.id, true, &|_| {}).await.unwrap();
+
+        assert_eq!(project.read("bracket.sldprt").unwrap(), b"bracket");
+        assert_eq!(project.read("plate.sldprt"), None);
+        assert_eq!(project.read("draft.sldprt").unwrap(), b"draft");
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn the_plan_points_out_uncommitted_files_it_would_replace() {
+        let remote_dir = TestDir::project(&SyncInfo::default());
+        remote_dir.write("parts/bracket.sldprt", b"dropped in by hand");
+        let project = TestDir::project(&SyncInfo::default());
+        project.write("parts/bracket.sldprt", b"bracket");
+        project.write("parts/plate.sldprt", b"plate");
+        let remote = folder_remote(&remote_dir);
+
+        let rows = [selected("parts/bracket.sldprt", FileStatus::LocalAdded), selected("parts/plate.sldprt", FileStatus::LocalAdded)];
+        let plan = plan_commit(&remote, &project.path, PROJECT, &rows, &|_| {}).await.unwrap();
+
+        assert_eq!(plan.upload.len(), 2);
+        assert_eq!(plan.replace_uncommitted, vec!["parts/bracket.sldprt".to_string()]);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn a_browsable_copy_that_cannot_be_written_is_a_warning() {
+        let remote_dir = TestDir::project(&SyncInfo::default());
+        // Something in the way of the browsable copy
+        remote_dir.write("bracket.sldprt/notes.txt", b"notes");
+        let project = TestDir::project(&SyncInfo::default());
+        project.write("bracket.sldprt", b"bracket");
+        let remote = folder_remote(&remote_dir);
+
+        let file = selected("bracket.sldprt", FileStatus::LocalAdded);
+        let outcome = commit(&remote, &project.path, PROJECT, &[file], None, "Add bracket".to_string(), Author::default(), false, 1, &|_| {}).await.unwrap();
+
+        assert_eq!(outcome.warnings.len(), 1);
+        assert_eq!(remote.read_manifest().await.unwrap().revision, Some(outcome.revision.id));
+    }
+
+    #[tokio::test]
+    async fn a_lock_keeps_everyone_else_out_until_it_is_given_up() {
+        let remote_dir = TestDir::new();
+        let project = TestDir::new();
+        project.write("bracket.sldprt", b"bracket");
+        let remote = folder_remote(&remote_dir);
+
+        lock(&remote, &project.path, "bracket.sldprt", "Ada".to_string(), "ada@example.com".to_string()).await.unwrap();
+        let taken = lock(&remote, &project.path, "bracket.sldprt", "Bob".to_string(), "bob@example.com".to_string()).await;
+        let unlocked = unlock(&remote, &project.path, "bracket.sldprt", "bob@example.com", false, false).await;
+
+        assert!(matches!(EntangleError::from(taken.unwrap_err()), EntangleError::Locked(_)));
+        assert!(matches!(EntangleError::from(unlocked.unwrap_err()), EntangleError::Locked(_)));
+        assert_eq!(remote.read_locks().await.unwrap().get("bracket.sldprt").unwrap().email, "ada@example.com");
+
+        unlock(&remote, &project.path, "bracket.sldprt", "ada@example.com", false, true).await.unwrap();
+        assert!(remote.read_locks().await.unwrap().get("bracket.sldprt").is_none());
+        assert!(fs::metadata(project.path.join("bracket.sldprt")).unwrap().permissions().readonly());
+
+        // Taking the lock makes the file editable again
+        lock(&remote, &project.path, "bracket.sldprt", "Bob".to_string(), "bob@example.com".to_string()).await.unwrap();
+        assert!(!fs::metadata(project.path.join("bracket.sldprt")).unwrap().permissions().readonly());
+    }
+
+    #[tokio::test]
+    async fn locking_takes_someone_signed_in() {
+        let remote_dir = TestDir::new();
+        let project = TestDir::new();
+        let remote = folder_remote(&remote_dir);
+
+        let result = lock(&remote, &project.path, "bracket.sldprt", String::new(), String::new()).await;
+
+        assert!(matches!(EntangleError::from(result.unwrap_err()), EntangleError::NotLoggedIn));
+        assert!(remote.read_locks().await.unwrap().get("bracket.sldprt").is_none());
+    }
+
+    #[tokio::test]
+    async fn someone_elses_lock_can_be_broken_with_force() {
+        let remote_dir = TestDir::new();
+        let project = TestDir::new();
+        let remote = folder_remote(&remote_dir);
+
+        lock(&remote, &project.path, "bracket.sldprt", "Ada".to_string(), "ada@example.com".to_string()).await.unwrap();
+        unlock(&remote, &project.path, "bracket.sldprt", "bob@example.com", true, false).await.unwrap();
+
+        assert!(remote.read_locks().await.unwrap().get("bracket.sldprt").is_none());
+    }
+
+    // A project and remote that both had bracket.sldprt at "base", since changed on both sides
+    fn conflicting() -> (TestDir, TestDir) {
+        let synced = |contents: &[u8]| SyncInfo {
+            files: vec![synced_file("parts/bracket.sldprt", contents)],
+            folders: vec!["parts".to_string()],
+            ..Default::default()
+        };
+        let remote_dir = TestDir::project(&synced(b"theirs"));
+        remote_dir.write("parts/bracket.sldprt", b"theirs");
+
+        let project = TestDir::project(&synced(b"base"));
+        project.write("parts/bracket.sldprt", b"mine");
+        (remote_dir, project)
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn pulling_a_conflict_keeps_both_versions() {
+        let (remote_dir, project) = conflicting();
+        let remote = folder_remote(&remote_dir);
+
+        pull(&remote, &project.path, PROJECT, &[selected("parts/bracket.sldprt", FileStatus::Conflict)]).await.unwrap();
+        let listed = status(&remote, &project.path, PROJECT, &|_| {}).await.unwrap();
+
+        assert_eq!(project.read("parts/bracket.sldprt").unwrap(), b"mine");
+        assert_eq!(project.read("parts/bracket.remote.sldprt").unwrap(), b"theirs");
+        // The copy isn't something to commit, and the file stays flagged until the conflict is resolved
+        assert_eq!(status_of(&listed, "parts/bracket.sldprt"), Some(FileStatus::Conflict));
+        assert_eq!(status_of(&listed, "parts/bracket.remote.sldprt"), None);
+        assert_eq!(read_baseline(&project).files[0].sha256, sha256_of(b"base"));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn conflicts_are_not_committed() {
+        let (remote_dir, project) = conflicting();
+        let remote = folder_remote(&remote_dir);
+
+        let file = selected("parts/bracket.sldprt", FileStatus::Conflict);
+        let result = commit(&remote, &project.path, PROJECT, &[file], None, "Mine".to_string(), Author::default(), false, 1, &|_| {}).await;
+
+        assert!(matches!(EntangleError::from(result.unwrap_err()), EntangleError::Conflict(_)));
+        assert_eq!(remote_dir.read("parts/bracket.sldprt").unwrap(), b"theirs");
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn keeping_the_remote_version_settles_the_conflict() {
+        let (remote_dir, project) = conflicting();
+        let remote = folder_remote(&remote_dir);
+        pull(&remote, &project.path, PROJECT, &[selected("parts/bracket.sldprt", FileStatus::Conflict)]).await.unwrap();
+
+        resolve_conflict(&project.path, PROJECT, "parts/bracket.sldprt", Keep::Remote).unwrap();
+        let listed = status(&remote, &project.path, PROJECT, &|_| {}).await.unwrap();
+
+        assert_eq!(project.read("parts/bracket.sldprt").unwrap(), b"theirs");
+        assert_eq!(project.read("parts/bracket.remote.sldprt"), None);
+        assert_eq!(status_of(&listed, "parts/bracket.sldprt"), Some(FileStatus::Unchanged));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn keeping_the_local_version_after_deleting_the_copy_by_hand() {
+        let (remote_dir, project) = conflicting();
+        let remote = folder_remote(&remote_dir);
+        pull(&remote, &project.path, PROJECT, &[selected("parts/bracket.sldprt", FileStatus::Conflict)]).await.unwrap();
+        fs::remove_file(project.path.join("parts/bracket.remote.sldprt")).unwrap();
+
+        resolve_conflict(&project.path, PROJECT, "parts/bracket.sldprt", Keep::Local).unwrap();
+
+        assert_eq!(project.read("parts/bracket.sldprt").unwrap(), b"mine");
+        assert!(read_baseline(&project).conflicts.is_empty());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn keeping_the_local_version_leaves_it_to_commit() {
+        let (remote_dir, project) = conflicting();
+        let remote = folder_remote(&remote_dir);
+        pull(&remote, &project.path, PROJECT, &[selected("parts/bracket.sldprt", FileStatus::Conflict)]).await.unwrap();
+
+        resolve_conflict(&project.path, PROJECT, "parts/bracket.sldprt", Keep::Local).unwrap();
+        let listed = status(&remote, &project.path, PROJECT, &|_| {}).await.unwrap();
+        let again = resolve_conflict(&project.path, PROJECT, "parts/bracket.sldprt", Keep::Local);
+
+        assert_eq!(project.read("parts/bracket.sldprt").unwrap(), b"mine");
+        assert_eq!(project.read("parts/bracket.remote.sldprt"), None);
+        assert_eq!(status_of(&listed, "parts/bracket.sldprt"), Some(FileStatus::LocalModified));
+        assert_eq!(again.unwrap_err().kind(), ErrorKind::NotFound);
+
+        commit(&remote, &project.path, PROJECT, &listed.files, listed.revision, "Mine".to_string(), Author::default(), false, 1, &|_| {}).await.unwrap();
+        assert_eq!(remote_dir.read("parts/bracket.sldprt").unwrap(), b"mine");
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn commits_are_checked_against_the_revision_they_were_listed_at() {
+        let remote_dir = TestDir::project(&SyncInfo::default());
+        let project = TestDir::project(&SyncInfo::default());
+        project.write("bracket.sldprt", b"bracket");
+        let remote = folder_remote(&remote_dir);
+
+        let listed = status(&remote, &project.path, PROJECT, &|_| {}).await.unwrap();
+        commit(&remote, &project.path, PROJECT, &listed.files, listed.revision.clone(), "Add bracket".to_string(), Author::default(), false, 1, &|_| {}).await.unwrap();
+
+        project.write("bracket.sldprt", b"bracket v2");
+        let file = selected("bracket.sldprt", FileStatus::LocalModified);
+        let stale = commit(&remote, &project.path, PROJECT, &[file], listed.revision, "Edit bracket".to_string(), Author::default(), false, 1, &|_| {}).await;
+
+        assert!(EntangleError::from(stale.unwrap_err()).to_string().contains("pull first"));
+        assert_eq!(remote_dir.read("bracket.sldprt").unwrap(), b"bracket");
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn a_file_deleted_on_the_remote_is_not_committed_over() {
+        // Pulled with the file, then someone else deleted it
+        let remote_dir = TestDir::project(&SyncInfo::default());
+        let project = TestDir::project(&SyncInfo { files: vec![synced_file("bracket.sldprt", b"bracket")], ..Default::default() });
+        project.write("bracket.sldprt", b"bracket v2");
+        let remote = folder_remote(&remote_dir);
+
+        let file = selected("bracket.sldprt", FileStatus::LocalModified);
+        let result = commit(&remote, &project.path, PROJECT, &[file], None, "Edit bracket".to_string(), Author::default(), false, 1, &|_| {}).await;
+
+        assert!(EntangleError::from(result.unwrap_err()).to_string().contains("pull first"));
+        assert_eq!(remote_dir.read("bracket.sldprt"), None);
+    }
+}
@@ -0,0 +1,107 @@
+use std::{fmt, io};
+
+use serde::Serialize;
+
+use crate::auth::LoginError;
+
+// Everything a command can fail with. The frontend gets it as {kind, message}, so it can tell the user what went wrong
+// instead of just that something did
+#[derive(Debug, Serialize)]
+#[serde(tag = "kind", content = "message")]
+pub(crate) enum EntangleError {
+    Io(String),
+    Drive(String),
+    Auth(String),
+    // A .sync, .history or .locks file that can't be read
+    Manifest(String),
+    Conflict(String),
+    Locked(String),
+    Network(String),
+    NotFound(String),
+    NotLoggedIn,
+}
+
+impl fmt::Display for EntangleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EntangleError::Io(e)
+            | EntangleError::Drive(e)
+            | EntangleError::Auth(e)
+            | EntangleError::Manifest(e)
+            | EntangleError::Conflict(e)
+            | EntangleError::Locked(e)
+            | EntangleError::Network(e)
+            | EntangleError::NotFound(e) => write!(f, "{}", e),
+            EntangleError::NotLoggedIn => write!(f, "Not logged in to Google Drive"),
+        }
+    }
+}
+
+impl std::error::Error for EntangleError {}
+
+impl EntangleError {
+    // The same kind of error, saying something else
+    pub(crate) fn with_message(&self, message: String) -> EntangleError {
+        match self {
+            EntangleError::Io(_) => EntangleError::Io(message),
+            EntangleError::Drive(_) => EntangleError::Drive(message),
+            EntangleError::Auth(_) => EntangleError::Auth(message),
+            EntangleError::Manifest(_) => EntangleError::Manifest(message),
+            EntangleError::Conflict(_) => EntangleError::Conflict(message),
+            EntangleError::Locked(_) => EntangleError::Locked(message),
+            EntangleError::Network(_) => EntangleError::Network(message),
+            EntangleError::NotFound(_) => EntangleError::NotFound(message),
+            EntangleError::NotLoggedIn => EntangleError::NotLoggedIn,
+        }
+    }
+}
+
+// Remotes and the engine work in io::Error, an EntangleError rides along inside it so the kind survives the trip
+impl From<EntangleError> for io::Error {
+    fn from(e: EntangleError) -> Self {
+        let kind = match e {
+            EntangleError::NotFound(_) => io::ErrorKind::NotFound,
+            EntangleError::Manifest(_) => io::ErrorKind::InvalidData,
+            _ => io::ErrorKind::Other,
+        };
+        io::Error::new(kind, e)
+    }
+}
+
+impl From<io::Error> for EntangleError {
+    fn from(e: io::Error) -> Self {
+        let kind = e.kind();
+        let message = e.to_string();
+
+        if let Some(inner) = e.into_inner() {
+            if let Ok(v) = inner.downcast::<EntangleError>() {
+                return *v;
+            }
+        }
+
+        match kind {
+            // serde_json errors turn into InvalidData when they pass through io::Error
+            io::ErrorKind::InvalidData => EntangleError::Manifest(message),
+            io::ErrorKind::NotFound => EntangleError::NotFound(message),
+            _ => EntangleError::Io(message),
+        }
+    }
+}
+
+impl From<serde_json::Error> for EntangleError {
+    fn from(e: serde_json::Error) -> Self {
+        EntangleError::Manifest(e.to_string())
+    }
+}
+
+impl From<reqwest::Error> for EntangleError {
+    fn from(e: reqwest::Error) -> Self {
+        EntangleError::Network(e.to_string())
+    }
+}
+
+impl From<LoginError> for EntangleError {
+    fn from(e: LoginError) -> Self {
+        EntangleError::Auth(e.to_string())
+    }
+}
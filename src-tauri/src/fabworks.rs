@@ -1,18 +1,21 @@
-use std::{path::PathBuf, fs::File, io::Read};
-use reqwest::multipart;
+use std::path::PathBuf;
 use serde::Deserialize;
-use tokio::fs;
 use walkdir::WalkDir;
 
+use crate::error::EntangleError;
+
 #[derive(Deserialize)]
 struct FWIDResponse {
     id: String
 }
 
-pub(crate) async fn list_fw_files(folderpath: String) -> Vec<String> {
-    WalkDir::new(folderpath.clone())
+pub(crate) async fn list_fw_files(folderpath: String) -> Result<Vec<String>, EntangleError> {
+    let entries = WalkDir::new(folderpath.clone())
         .into_iter()
-        .filter_map(|entry| entry.ok())
+        .collect::<Result<Vec<_>, walkdir::Error>>()
+        .map_err(std::io::Error::from)?;
+
+    Ok(entries.into_iter()
         .filter(|entry| !entry.file_type().is_dir())
         .filter(|entry| {
             let file_name = entry.file_name().to_string_lossy().to_lowercase();
@@ -20,12 +23,12 @@ pub(crate) async fn list_fw_files(folderpath: String) -> Vec<String> {
         })
         .map(|entry| {
             let relative_path = entry.path().strip_prefix(folderpath.clone()).unwrap();
-            relative_path.to_str().unwrap().to_string()
+            relative_path.to_string_lossy().into_owned()
         })
-        .collect()
+        .collect())
 }
 
-pub(crate) async fn push_to_fw(folderpath: String, files: Vec<String>) -> Result<(), reqwest::Error> {
+pub(crate) async fn push_to_fw(folderpath: String, files: Vec<String>) -> Result<(), EntangleError> {
     let create_url = "https://www.fabworks.com/api/quotes/create";
     let create_response = reqwest::get(create_url).await?;
     
@@ -39,25 +42,25 @@ pub(crate) async fn push_to_fw(folderpath: String, files: Vec<String>) -> Result
         let upload_url = "https://www.fabworks.com/api/quotes/upload";
         let form = reqwest::blocking::multipart::Form::new()
             .text("id", create_id.clone())
-            .file("file", full_path).unwrap();
+            .file("file", full_path)?;
 
         tokio::task::spawn_blocking(move || {
             let client = reqwest::blocking::Client::new();
             let response = client
                 .post(upload_url)
                 .multipart(form)
-                .send().unwrap();
+                .send()?;
 
             if response.status().is_success() {
-                println!("File '{}' uploaded successfully", file_path);
+                Ok(())
             } else {
-                eprintln!("Failed to upload file '{}': {}", file_path, response.status());
+                Err(EntangleError::Network(format!("Failed to upload file '{}' to Fabworks: {}", file_path, response.status())))
             }
-        }).await.unwrap();
+        }).await.map_err(|e| EntangleError::Io(e.to_string()))??;
     }
 
     let url = format!("https://www.fabworks.com/quotes/{}", create_id.clone());
-    webbrowser::open(&url).unwrap();
+    webbrowser::open(&url)?;
 
     Ok(())
 }
\ No newline at end of file
@@ -0,0 +1,140 @@
+use std::{future::Future, io::Error, sync::Arc};
+
+use google_drive::{Client, ClientError};
+use tokio::sync::Mutex;
+
+use crate::auth::{self, GDStruct, LoginError};
+use crate::credentials;
+use crate::error::EntangleError;
+
+// A Google Drive login shared between State and every remote made from it. Drive requests go through here, so an
+// access token that runs out mid-pull is refreshed once, saved, and the request retried instead of failing
+#[derive(Clone)]
+pub(crate) struct DriveSession {
+    gds: Arc<Mutex<GDStruct>>,
+    email: Option<String>,
+    api_root: String,
+}
+
+const DRIVE_API_ROOT: &str = "https://www.googleapis.com";
+
+impl DriveSession {
+    pub fn new(gds: GDStruct) -> DriveSession {
+        DriveSession {
+            email: gds.email.clone(),
+            gds: Arc::new(Mutex::new(gds)),
+            api_root: DRIVE_API_ROOT.to_string(),
+        }
+    }
+
+    // Send every request to a stand-in for the Drive API instead
+    #[cfg(test)]
+    pub fn with_api_root(mut self, api_root: &str) -> DriveSession {
+        self.api_root = api_root.to_string();
+        self
+    }
+
+    // The address of a Drive API path, like /drive/v3/files
+    pub fn api_url(&self, path: &str) -> String {
+        format!("{}{}", self.api_root, path)
+    }
+
+    // The Google account that signed in
+    pub fn email(&self) -> Option<String> {
+        self.email.clone()
+    }
+
+    // Whether the login still works, or can be made to work again without the user
+    pub async fn is_usable(&self) -> bool {
+        let gds = self.gds.lock().await;
+        !gds.is_expired() || auth::can_refresh(&gds.provider)
+    }
+
+    // The current login, refreshed first if the access token is about to run out
+    pub async fn current(&self) -> Result<GDStruct, Error> {
+        let mut gds = self.gds.lock().await;
+        if gds.is_expired() {
+            refresh_locked(&mut gds).await?;
+        }
+        Ok(gds.clone())
+    }
+
+    // Drive turned down the access token in stale. Requests run in parallel, so it is only refreshed if no other
+    // request has refreshed it already
+    async fn refresh(&self, stale: &GDStruct) -> Result<GDStruct, Error> {
+        let mut gds = self.gds.lock().await;
+        if gds.token.access_token == stale.token.access_token {
+            refresh_locked(&mut gds).await?;
+        }
+        Ok(gds.clone())
+    }
+
+    // Send a request built by build with the current login, retrying once with a refreshed one on a 401
+    pub async fn send<F>(&self, build: F) -> Result<reqwest::Response, Error>
+    where
+        F: Fn(&GDStruct) -> reqwest::RequestBuilder,
+    {
+        let gds = self.current().await?;
+        let response = build(&gds).send().await.map_err(http_error)?;
+
+        if response.status() != reqwest::StatusCode::UNAUTHORIZED {
+            return Ok(response);
+        }
+
+        let gds = self.refresh(&gds).await?;
+        build(&gds).send().await.map_err(http_error)
+    }
+
+    // Run a google_drive client call with the current login, retrying once with a refreshed one on a 401
+    pub async fn call<T, F, Fut>(&self, f: F) -> Result<T, Error>
+    where
+        F: Fn(Client) -> Fut,
+        Fut: Future<Output = Result<google_drive::Response<T>, ClientError>>,
+    {
+        let gds = self.current().await?;
+
+        match f(self.client(&gds)).await {
+            Ok(v) => Ok(v.body),
+            Err(e) if is_unauthorized(&e) => {
+                let gds = self.refresh(&gds).await?;
+                f(self.client(&gds)).await.map(|v| v.body).map_err(drive_error)
+            }
+            Err(e) => Err(drive_error(e)),
+        }
+    }
+
+    fn client(&self, gds: &GDStruct) -> Client {
+        let mut client = gds.drive.clone();
+        if self.api_root != DRIVE_API_ROOT {
+            client.with_host_override(self.api_url("/drive/v3"));
+        }
+        client
+    }
+}
+
+// Never opens a browser, refreshing happens with the session locked and every Drive request waiting on it
+async fn refresh_locked(gds: &mut GDStruct) -> Result<(), Error> {
+    // An invalid_grant here means the refresh token was revoked, only logging in again fixes that
+    *gds = auth::refresh(gds).await.map_err(|e| match e {
+        LoginError::Expired => EntangleError::NotLoggedIn,
+        e => EntangleError::Auth(format!("Failed to refresh Google Drive login: {}", e)),
+    })?;
+    credentials::save_session(gds);
+    Ok(())
+}
+
+fn is_unauthorized(e: &ClientError) -> bool {
+    matches!(e, ClientError::HttpError { status, .. } if status.as_u16() == 401)
+}
+
+fn drive_error(e: ClientError) -> Error {
+    // Lets callers tell an id that has gone stale apart from other failures
+    if matches!(&e, ClientError::HttpError { status, .. } if status.as_u16() == 404) {
+        return EntangleError::NotFound(format!("Google Drive request failed: {}", e)).into();
+    }
+    EntangleError::Drive(format!("Google Drive request failed: {}", e)).into()
+}
+
+fn http_error(e: reqwest::Error) -> Error {
+    EntangleError::Network(format!("Google Drive request failed: {}", e)).into()
+}
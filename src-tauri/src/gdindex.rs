@@ -0,0 +1,174 @@
+use std::{fs, path::{Component, Path, PathBuf}, sync::{Mutex, atomic::{AtomicBool, Ordering}}};
+
+use crate::GDriveIDs;
+
+impl GDriveIDs {
+    fn get(&self, path: &str) -> Option<String> {
+        self.ids.get(path).cloned()
+    }
+
+    fn is_folder(&self, path: &str) -> bool {
+        self.folders.contains(path)
+    }
+
+    pub fn insert(&mut self, path: String, id: String, parent_id: String, is_folder: bool) {
+        if is_folder {
+            self.folders.insert(path.clone());
+        }
+        self.parents.insert(id.clone(), parent_id);
+        self.ids.insert(path, id);
+    }
+
+    // Forget path and, if it's a folder, everything below it
+    fn remove(&mut self, path: &str) {
+        let prefix = format!("{}/", path);
+        let stale: Vec<String> = self.ids.keys()
+            .filter(|k| *k == path || k.starts_with(&prefix))
+            .cloned()
+            .collect();
+
+        for k in stale {
+            if let Some(id) = self.ids.remove(&k) {
+                self.parents.remove(&id);
+            }
+            self.folders.remove(&k);
+        }
+    }
+}
+
+// Paths below a Drive project folder resolved to file ids. Drive only finds files by name one folder at a time, so
+// without this every file costs a query per folder on its path. Built from a full listing of the project, kept up to
+// date as files are created and deleted, and thrown away whenever Drive says an id no longer exists
+pub(crate) struct DriveIndex {
+    folder_id: String,
+    ids: Mutex<GDriveIDs>,
+    built: AtomicBool,
+    dirty: AtomicBool,
+    persist: bool,
+    // Held while folders are created, so uploads running side by side don't each make the same one
+    creating: tokio::sync::Mutex<()>,
+}
+
+impl DriveIndex {
+    // Picks up the index saved by the last remote made for folder_id, if there was one
+    pub fn load(folder_id: &str) -> DriveIndex {
+        let saved = cache_path(folder_id)
+            .and_then(|pth| fs::read(pth).ok())
+            .and_then(|bytes| serde_json::from_slice::<GDriveIDs>(&bytes).ok());
+
+        DriveIndex {
+            folder_id: folder_id.to_string(),
+            built: AtomicBool::new(saved.is_some()),
+            ids: Mutex::new(saved.unwrap_or_default()),
+            dirty: AtomicBool::new(false),
+            persist: true,
+            creating: tokio::sync::Mutex::new(()),
+        }
+    }
+
+    // An index that starts out empty and is never saved
+    #[cfg(test)]
+    pub fn in_memory(folder_id: &str) -> DriveIndex {
+        DriveIndex {
+            folder_id: folder_id.to_string(),
+            ids: Mutex::new(GDriveIDs::default()),
+            built: AtomicBool::new(false),
+            dirty: AtomicBool::new(false),
+            persist: false,
+            creating: tokio::sync::Mutex::new(()),
+        }
+    }
+
+    pub fn folder_id(&self) -> &str {
+        &self.folder_id
+    }
+
+    // Whether the index has been filled in from a listing of the project yet
+    pub fn is_built(&self) -> bool {
+        self.built.load(Ordering::Relaxed)
+    }
+
+    // The id of a path made with path_key, the project folder itself is ""
+    pub fn get(&self, key: &str) -> Option<String> {
+        if key.is_empty() {
+            return Some(self.folder_id.clone());
+        }
+        self.ids.lock().unwrap().get(key)
+    }
+
+    pub async fn lock_folders(&self) -> tokio::sync::MutexGuard<'_, ()> {
+        self.creating.lock().await
+    }
+
+    pub fn is_folder(&self, key: &str) -> bool {
+        key.is_empty() || self.ids.lock().unwrap().is_folder(key)
+    }
+
+    pub fn insert(&self, key: String, id: String, parent_id: String, is_folder: bool) {
+        self.ids.lock().unwrap().insert(key, id, parent_id, is_folder);
+        self.dirty.store(true, Ordering::Relaxed);
+    }
+
+    // Drive no longer knows the id cached for key
+    pub fn invalidate(&self, key: &str) {
+        self.ids.lock().unwrap().remove(key);
+        self.dirty.store(true, Ordering::Relaxed);
+    }
+
+    // Swap in the ids from a fresh listing of the whole project
+    pub fn replace(&self, ids: GDriveIDs) {
+        *self.ids.lock().unwrap() = ids;
+        self.built.store(true, Ordering::Relaxed);
+        self.dirty.store(true, Ordering::Relaxed);
+    }
+
+    fn save(&self) {
+        let pth = match cache_path(&self.folder_id) {
+            Some(v) => v,
+            None => return,
+        };
+
+        let result = fs::create_dir_all(pth.parent().unwrap())
+            .and_then(|_| fs::write(&pth, serde_json::to_vec(&*self.ids.lock().unwrap())?));
+        if let Err(e) = result {
+            eprintln!("Failed to save Drive id cache: {}", e);
+        }
+    }
+}
+
+// Remotes only live as long as a command, the index is written out once at the end instead of on every change
+impl Drop for DriveIndex {
+    fn drop(&mut self) {
+        if self.persist && self.dirty.load(Ordering::Relaxed) {
+            self.save();
+        }
+    }
+}
+
+fn cache_path(folder_id: &str) -> Option<PathBuf> {
+    dirs::cache_dir().map(|v| v.join("Entangle").join("driveids").join(format!("{}.json", folder_id)))
+}
+
+// Throw away the saved index for a project folder, the next remote made for it lists the project again
+pub(crate) fn clear_index(folder_id: &str) -> Result<(), std::io::Error> {
+    match cache_path(folder_id) {
+        Some(pth) if pth.exists() => fs::remove_file(pth),
+        _ => Ok(()),
+    }
+}
+
+// Drive paths are /-separated whatever the platform, and relative to the project folder
+pub(crate) fn path_key(path: &Path) -> String {
+    path.components()
+        .filter_map(|c| match c {
+            Component::Normal(v) => Some(v.to_string_lossy().into_owned()),
+            _ => None,
+        })
+        .collect::<Vec<String>>()
+        .join("/")
+}
+
+// The folder a key is in, "" for the project folder
+pub(crate) fn parent_key(key: &str) -> &str {
+    key.rsplit_once('/').map(|(parent, _)| parent).unwrap_or("")
+}
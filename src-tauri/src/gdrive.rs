@@ -1,452 +1,916 @@
-use std::{path::{Path, PathBuf, Component}, fs::{File, self}, io::Read, sync::Arc, collections::HashMap};
-
-use google_drive::{Client, AccessToken, traits::FileOps};
-use tokio::sync::{Semaphore, Mutex};
-use vfs::{VfsPath, MemoryFS};
+use std::{path::{Path, Component}, io::{Error, ErrorKind}};
+
+use async_trait::async_trait;
+use google_drive::traits::FileOps;
+use reqwest::Method;
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::{SyncInfo, GDriveIDs};
+use crate::error::EntangleError;
+use crate::gdhttp::DriveSession;
+use crate::gdindex::{DriveIndex, path_key, parent_key};
+use crate::gdupload::{self, OnUploadProgress, SavedUpload, SessionState, UploadProgress, UploadSessions, file_version};
+use crate::history::{History, Revision, parse_history, parse_revision, revision_path};
+use crate::lock::{Locks, parse_locks};
+use crate::objects::{META_DIR, OBJECTS_DIR};
+use crate::remote::Remote;
+
+// Every file matching a Drive search query
+async fn gd_query(session: &DriveSession, drive_id: &str, query: &str) -> Result<Vec<google_drive::types::File>, Error> {
+    session.call(|client| async move {
+        client.files().list_all(
+            "allDrives",  // corpora
+            drive_id, // drive id
+            true,     // include_items_from_all_drives
+            "",       // include_permissions_for_view
+            false,    // include_team_drive_items
+            "",       // order_by
+            query,    // query
+            "",       // spaces
+            true,     // supports_all_drives
+            false,    // supports_team_drives
+            "",       // team_drive_id
+        )
+        .await
+    })
+    .await
+}
 
 pub(crate) async fn gd_get_sync(
     folder_id: &str,
-    client: &Client,
-    tokens: &AccessToken,
-) -> String {
-
-    let mut query = format!("name contains '.sync'");
-    query = format!("{} and '{}' in parents and trashed = false", query, folder_id);
-
-    let files = client.files().list_all(
-        "allDrives",  // corpora
-        "", // drive id
-        true,     // include_items_from_all_drives
-        "",       // include_permissions_for_view
-        false,    // include_team_drive_items
-        "",       // order_by
-        &query,   // query
-        "",       // spaces
-        true,     // supports_all_drives
-        false,    // supports_team_drives
-        "",       // team_drive_id
-    )
-    .await;
-
-    let filesvec = files.unwrap().body;
-
-    if filesvec.is_empty() {
-        return "".to_string();
-    }else{
-        let fl = filesvec.get(0).unwrap();
-
-        return fl.name.clone();
+    session: &DriveSession,
+) -> Result<String, Error> {
+
+    let query = format!("name contains '.sync' and '{}' in parents and trashed = false", folder_id);
+
+    let filesvec = gd_query(session, "", &query).await?;
+
+    match filesvec.first() {
+        Some(fl) => Ok(fl.name.clone()),
+        None => Err(EntangleError::NotFound("There is no .sync file in that Drive folder".to_string()).into()),
     }
 }
 
-pub(crate) async fn upload_files_to_google_drive(
-    files: Vec<Box<Path>>,
-    folder_path: &str,
-    folder_id: &str,
-    client: &Client,
-    tokens: &AccessToken,
-    override_name: Option<String>
-) {
-    let drive_id = &client.files().get(folder_id, false, "published", true, true).await.unwrap().body.drive_id;
-    let mut folder_id_tmp: String = folder_id.to_string();
-    for file in files {
-        let file_path = file.clone();
-        let relative_path = file_path.strip_prefix(Path::new(folder_path)).unwrap_or(&(*file_path));
-        let full_destination_path = PathBuf::from(folder_id_tmp.clone()).join(relative_path);
-        let mut current_parent_id = folder_id_tmp.to_string();
-
-        if let Some(file_name) = file_path.file_name() {
-            let file_name_str = file_name.to_str().unwrap();
-
-            for component in relative_path.parent().unwrap_or(Path::new("")).components() {
-                let component_str = component.as_os_str().to_str().unwrap();
-                //let result = client.files().create_folder(drive_id, &current_parent_id, component_str).await.unwrap();
-                
-                let folder_mime_type = "application/vnd.google-apps.folder";
-                let mut file: google_drive::types::File = Default::default();
-                // Set the name,
-                file.name = component_str.to_string();
-                file.mime_type = folder_mime_type.to_string();
-                file.parents = vec![current_parent_id.clone()];
-
-                let mut query = format!(
-                    "name = '{}' and mimeType = 'application/vnd.google-apps.folder'",
-                    component_str
-                );
-                query = format!("{} and '{}' in parents and trashed = false", query, current_parent_id.clone());
-        
-                // Check if the folder exists.
-                let resp = client.files()
-                    .list_all(
-                        "allDrives",  // corpora
-                        "", // drive id
-                        true,     // include_items_from_all_drives
-                        "",       // include_permissions_for_view
-                        false,    // include_team_drive_items
-                        "",       // order_by
-                        &query,   // query
-                        "",       // spaces
-                        true,     // supports_all_drives
-                        false,    // supports_team_drives
-                        "",       // team_drive_id
-                    )
-                    .await.unwrap();
-        
-                if !resp.body.is_empty() {
-                    current_parent_id = resp.body.get(0).unwrap().id.clone();
-                }else{
-                    let res = client.files().create(false, "published", false, "en", true, true, false, &file).await.unwrap();
-                    current_parent_id = res.body.id;
-                }
-
-                println!("RE: {:?}", resp.body);
-
-                // Use the created or existing folder ID for the next iterations
-                //current_parent_id = result.body.id;
-            }
+// The last component of a relative path, Drive looks files up by name inside their parent folder
+fn file_name(path: &Path) -> Result<&str, Error> {
+    path.file_name()
+        .and_then(|v| v.to_str())
+        .ok_or_else(|| EntangleError::Io(format!("{} is not a valid file name", path.display())).into())
+}
 
-            // Check if the file is a folder
-            if file_path.is_dir() {
-                // Create the folder in Google Drive
-                
-            } else {
-                
-            }
+const FOLDER_MIME_TYPE: &str = "application/vnd.google-apps.folder";
 
-            // Read the file contents
-            let mut file_contents = Vec::new();
-            let _ = File::open(file.clone()).unwrap()
-                .read_to_end(&mut file_contents);
-
-            let name_final = override_name.clone().unwrap_or(file_name_str.to_owned());
-            
-            let mut query = format!("name = '{}'", name_final);
-            query = format!("{} and '{}' in parents and trashed = false", query, current_parent_id);
-
-            let files = client.files().list_all(
-                "allDrives",  // corpora
-                drive_id, // drive id
-                true,     // include_items_from_all_drives
-                "",       // include_permissions_for_view
-                false,    // include_team_drive_items
-                "",       // order_by
-                &query,   // query
-                "",       // spaces
-                true,     // supports_all_drives
-                false,    // supports_team_drives
-                "",       // team_drive_id
-            )
-            .await;
-
-            let file = files.unwrap();
-
-            println!("TEST {} {:?}", current_parent_id, file.body);
-
-            if file.body.len() != 0 {
-                for fl in file.body.clone() {
-                    println!("{:?}", fl.parents);
-                }
-                let file_bd = file.body.get(0).unwrap();
-
-                let client = reqwest::Client::new();
-
-                let mut req: reqwest::RequestBuilder = client.patch(format!("https://www.googleapis.com/upload/drive/v3/files/{}", &file_bd.id));
-                req = req.header("Authorization", format!("Bearer {}", tokens.access_token));
-                req = req.header("Content-Length", file_contents.len().to_string());
-                req = req.header("Content-Type", "application/octet-stream");
-                req = req.query(&[("uploadType", "media")]);
-                req = req.body(file_contents);
-                let fullreq = req.build().unwrap();
-                let response = client.execute(fullreq).await.unwrap();
-                println!("{}", response.text().await.unwrap());
-            }else{
-                client.files().create_or_update(
-                    "",
-                    &current_parent_id,
-                    &name_final,
-                    "application/octet-stream", // Replace with the actual mime type
-                    &file_contents,
-                ).await.unwrap();
-            }
+// The file called name directly inside parent_id, straight from Drive
+async fn gd_find_child(
+    name: &str,
+    parent_id: &str,
+    session: &DriveSession,
+) -> Result<Option<google_drive::types::File>, Error> {
+    let query = format!("name = '{}' and '{}' in parents and trashed = false", name, parent_id);
+    Ok(gd_query(session, "", &query).await?.into_iter().next())
+}
+
+// The id of the file at key, from the index where possible. Anything the index doesn't know about is looked up on
+// Drive, since other people add files between listings
+async fn gd_find(
+    key: &str,
+    index: &DriveIndex,
+    session: &DriveSession,
+) -> Result<Option<String>, Error> {
+    // The first lookup for a project with nothing saved lists the whole project once, instead of walking every path
+    if !index.is_built() {
+        gd_list(index, session).await?;
+    }
+
+    if let Some(id) = index.get(key) {
+        if gd_is_live(&id, session).await? {
+            return Ok(Some(id));
+        }
+        // Trashed or deleted since it was indexed, whatever is there now is looked up below
+        index.invalidate(key);
+    }
+
+    let parent = parent_key(key);
+    let parent_id = match gd_resolve_folder(parent, index, session).await? {
+        Some(v) => v,
+        // Nothing can be in a folder that doesn't exist
+        None => return Ok(None),
+    };
+    let name = file_name(Path::new(key))?;
+
+    match gd_find_child(name, &parent_id, session).await? {
+        Some(fl) => {
+            index.insert(key.to_string(), fl.id.clone(), parent_id, fl.mime_type == FOLDER_MIME_TYPE);
+            Ok(Some(fl.id))
         }
+        None => Ok(None),
     }
 }
 
-pub(crate) async fn gd_get_file(
-    files_name: &str,
-    folder_id: &str,
-    client: &Client,
-    tokens: &AccessToken,
-) -> Option<Vec<u8>> {
+#[derive(Deserialize)]
+struct FileState {
+    #[serde(default)]
+    trashed: bool,
+}
 
-    let mut parentid = folder_id.to_string();
+// Whether the file with this id is still there. Drive keeps serving a trashed file by its id, downloads and updates
+// included, so only asking for trashed tells it apart from a live one
+async fn gd_is_live(id: &str, session: &DriveSession) -> Result<bool, Error> {
+    let link = session.api_url(&format!("/drive/v3/files/{}?fields=trashed&supportsAllDrives=true", id));
+    let response = session.send(|gds| {
+        reqwest::Client::new().get(link.clone()).header("Authorization", format!("Bearer {}", gds.token.access_token))
+    }).await?;
 
-    let mut fl_name = files_name;
+    if response.status() == reqwest::StatusCode::NOT_FOUND {
+        return Ok(false);
+    }
+    if !response.status().is_success() {
+        return Err(EntangleError::Drive(format!("Failed to look up file {}: {}", id, response.status())).into());
+    }
 
-    if files_name.contains("/") {
-        let path = Path::new(files_name);
-        fl_name = path.file_name().unwrap().to_str().unwrap();
+    let state: FileState = response.json().await.map_err(|e| EntangleError::Drive(format!("Unexpected response looking up file {}: {}", id, e)))?;
+    Ok(!state.trashed)
+}
 
-        for component in path.parent().unwrap_or(Path::new("")).components() {
-            let component_str = component.as_os_str().to_str().unwrap();
-            //let result = client.files().create_folder(drive_id, &current_parent_id, component_str).await.unwrap();
-            
-            let folder_mime_type = "application/vnd.google-apps.folder";
-            let mut file: google_drive::types::File = Default::default();
-            // Set the name,
-            file.name = component_str.to_string();
-            file.mime_type = folder_mime_type.to_string();
-            file.parents = vec![parentid.to_string()];
-
-            let mut query = format!(
-                "name = '{}' and mimeType = 'application/vnd.google-apps.folder'",
-                component_str
-            );
-            query = format!("{} and '{}' in parents and trashed = false", query, parentid.clone());
-    
-            // Check if the folder exists.
-            let resp = client.files()
-                .list_all(
-                    "allDrives",  // corpora
-                    "", // drive id
-                    true,     // include_items_from_all_drives
-                    "",       // include_permissions_for_view
-                    false,    // include_team_drive_items
-                    "",       // order_by
-                    &query,   // query
-                    "",       // spaces
-                    true,     // supports_all_drives
-                    false,    // supports_team_drives
-                    "",       // team_drive_id
-                )
-                .await.unwrap();
-    
-            if !resp.body.is_empty() {
-                parentid = resp.body.get(0).unwrap().id.clone();
-            }else{
-                let res = client.files().create(false, "published", false, "en", true, true, false, &file).await.unwrap();
-                parentid = res.body.id;
+// Upload a small file held in memory, like the manifest, to relative_path in the project folder, creating any missing
+// parent folders and replacing the file if it already exists. Project files go through gd_upload_file instead
+pub(crate) async fn gd_upload(
+    relative_path: &Path,
+    file_contents: Vec<u8>,
+    index: &DriveIndex,
+    session: &DriveSession,
+) -> Result<(), Error> {
+    let key = path_key(relative_path);
+    let file_name_str = file_name(relative_path)?;
+
+    if let Some(id) = gd_find(&key, index, session).await? {
+        let response = session.send(|gds| {
+            let mut req: reqwest::RequestBuilder = reqwest::Client::new().patch(session.api_url(&format!("/upload/drive/v3/files/{}", &id)));
+            req = req.header("Authorization", format!("Bearer {}", gds.token.access_token));
+            req = req.header("Content-Length", file_contents.len().to_string());
+            req = req.header("Content-Type", "application/octet-stream");
+            req = req.query(&[("uploadType", "media")]);
+            req.body(file_contents.clone())
+        }).await?;
+
+        if response.status() != reqwest::StatusCode::NOT_FOUND {
+            if !response.status().is_success() {
+                return Err(EntangleError::Drive(format!("Failed to upload {}: {}", relative_path.display(), response.status())).into());
             }
-            println!("RE: {:?}", resp.body);
+            return Ok(());
         }
-    }
 
-    let mut query = format!("name = '{}'", fl_name);
-    query = format!("{} and '{}' in parents and trashed = false", query, parentid);
+        // Someone deleted the file since it was indexed, it gets created again below
+        index.invalidate(&key);
+    }
 
-    let files = client.files().list_all(
-        "allDrives",  // corpora
-        "", // drive id
-        true,     // include_items_from_all_drives
-        "",       // include_permissions_for_view
-        false,    // include_team_drive_items
-        "",       // order_by
-        &query,   // query
-        "",       // spaces
-        true,     // supports_all_drives
-        false,    // supports_team_drives
-        "",       // team_drive_id
-    )
-    .await;
+    let mut current_parent_id = gd_ensure_folder(Path::new(parent_key(&key)), index, session).await?;
+    let mut created = gd_create(file_name_str, &file_contents, &current_parent_id, session).await;
 
-    let filesvec = files.unwrap().body;
+    // Same for a parent folder deleted since it was indexed
+    if matches!(&created, Err(e) if e.kind() == ErrorKind::NotFound) {
+        index.invalidate(parent_key(&key));
+        current_parent_id = gd_ensure_folder(Path::new(parent_key(&key)), index, session).await?;
+        created = gd_create(file_name_str, &file_contents, &current_parent_id, session).await;
+    }
 
-    if filesvec.is_empty() {
-        return Some(Vec::new());
-    }else{
-        let fl = filesvec.get(0).unwrap();
+    let created = created?;
+    if !created.id.is_empty() {
+        index.insert(key, created.id, current_parent_id, false);
+    }
+    Ok(())
+}
 
-        println!("{}", fl.mime_type);
+// Upload local_file to relative_path through a resumable session, a chunk at a time, creating missing parent folders
+// and replacing the file if it already exists. An earlier upload of the same file that was cut off carries on from
+// where it stopped. Progress is reported under reported_path, the file the user knows the upload as
+pub(crate) async fn gd_upload_file(
+    relative_path: &Path,
+    reported_path: &str,
+    local_file: &Path,
+    index: &DriveIndex,
+    uploads: &UploadSessions,
+    session: &DriveSession,
+    on_progress: Option<&OnUploadProgress>,
+) -> Result<(), Error> {
+    let key = path_key(relative_path);
+    let (size, modified) = file_version(local_file)?;
+    let report = |bytes_done: u64| {
+        if let Some(f) = on_progress {
+            f(UploadProgress { path: reported_path.to_string(), bytes_done, bytes_total: size });
+        }
+    };
 
-        if fl.mime_type == "application/vnd.google-apps.folder" {
-            return None;
+    if let Some(saved) = uploads.get(&key, size, modified) {
+        let result = match gdupload::session_state(&saved.uri, size, session).await? {
+            SessionState::Offset(offset) => {
+                report(offset);
+                Some(gdupload::send_chunks(&saved.uri, local_file, size, offset, session, &report).await)
+            }
+            SessionState::Done(id) => Some(Ok(id)),
+            SessionState::Expired => None,
+        };
+
+        match result {
+            Some(Ok(id)) => return gd_finish_upload(&key, id, saved.parent_id, index, uploads),
+            // Expired, so it starts over below
+            Some(Err(e)) if e.kind() == ErrorKind::NotFound => {}
+            // Anything else leaves the session for the next try
+            Some(Err(e)) => return Err(e),
+            None => {}
         }
+        uploads.remove(&key);
+    }
 
-        let mut link = format!("https://www.googleapis.com/drive/v3/files/{}?alt=media", fl.id);
+    let (uri, parent_id) = gd_start_upload(&key, size, index, session).await?;
+    uploads.insert(&key, SavedUpload::new(uri.clone(), size, modified, parent_id.clone()));
 
-        let client = reqwest::Client::new();
-        let req = client.get(link.clone()).header("Authorization", format!("Bearer {}", tokens.access_token)).send().await.unwrap();
+    report(0);
+    let id = gdupload::send_chunks(&uri, local_file, size, 0, session, &report).await?;
+    gd_finish_upload(&key, id, parent_id, index, uploads)
+}
 
-        let body = req.bytes().await;
-        return Some(body.unwrap().to_vec());
+fn gd_finish_upload(key: &str, id: String, parent_id: Option<String>, index: &DriveIndex, uploads: &UploadSessions) -> Result<(), Error> {
+    uploads.remove(key);
+    if let Some(parent_id) = parent_id {
+        index.insert(key.to_string(), id, parent_id, false);
     }
+    Ok(())
 }
 
-pub(crate) async fn gd_delete_file(
-    files_name: &str,
-    folder_id: &str,
-    client: &Client,
-    tokens: &AccessToken,
-) {
+// Open an upload session for key, replacing the file there or creating it (and its folders) if there isn't one.
+// Returns the session and, for a new file, the folder it goes into
+async fn gd_start_upload(
+    key: &str,
+    size: u64,
+    index: &DriveIndex,
+    session: &DriveSession,
+) -> Result<(String, Option<String>), Error> {
+    if let Some(id) = gd_find(key, index, session).await? {
+        let url = session.api_url(&format!("/upload/drive/v3/files/{}?uploadType=resumable&supportsAllDrives=true", id));
+        if let Some(uri) = gdupload::start_session(Method::PATCH, &url, &json!({}), size, session).await? {
+            return Ok((uri, None));
+        }
 
-    let mut parentid = folder_id.to_string();
+        // Someone deleted the file since it was indexed, it gets created again below
+        index.invalidate(key);
+    }
 
-    let mut fl_name = files_name;
+    let name = file_name(Path::new(key))?;
+    let url = session.api_url("/upload/drive/v3/files?uploadType=resumable&supportsAllDrives=true");
 
-    if files_name.contains("/") {
-        let path = Path::new(files_name);
-        fl_name = path.file_name().unwrap().to_str().unwrap();
+    // Same for a parent folder deleted since it was indexed, it is looked up again once
+    for _ in 0..2 {
+        let parent_id = gd_ensure_folder(Path::new(parent_key(key)), index, session).await?;
+        let metadata = json!({ "name": name, "parents": [parent_id] });
 
-        for component in path.parent().unwrap_or(Path::new("")).components() {
-            let component_str = component.as_os_str().to_str().unwrap();
-            //let result = client.files().create_folder(drive_id, &current_parent_id, component_str).await.unwrap();
-            
-            let folder_mime_type = "application/vnd.google-apps.folder";
-            let mut file: google_drive::types::File = Default::default();
-            // Set the name,
-            file.name = component_str.to_string();
-            file.mime_type = folder_mime_type.to_string();
-            file.parents = vec![parentid.to_string()];
-
-            let mut query = format!(
-                "name = '{}' and mimeType = 'application/vnd.google-apps.folder'",
-                component_str
-            );
-            query = format!("{} and '{}' in parents and trashed = false", query, parentid.clone());
-    
-            // Check if the folder exists.
-            let resp = client.files()
-                .list_all(
-                    "allDrives",  // corpora
-                    "", // drive id
-                    true,     // include_items_from_all_drives
-                    "",       // include_permissions_for_view
-                    false,    // include_team_drive_items
-                    "",       // order_by
-                    &query,   // query
-                    "",       // spaces
-                    true,     // supports_all_drives
-                    false,    // supports_team_drives
-                    "",       // team_drive_id
-                )
-                .await.unwrap();
-    
-            if !resp.body.is_empty() {
-                parentid = resp.body.get(0).unwrap().id.clone();
-            }else{
-                let res = client.files().create(false, "published", false, "en", true, true, false, &file).await.unwrap();
-                parentid = res.body.id;
-            }
-            println!("RE: {:?}", resp.body);
+        if let Some(uri) = gdupload::start_session(Method::POST, &url, &metadata, size, session).await? {
+            return Ok((uri, Some(parent_id)));
         }
+        index.invalidate(parent_key(key));
     }
 
-    let mut query = format!("name = '{}'", fl_name);
-    query = format!("{} and '{}' in parents and trashed = false", query, parentid);
+    Err(EntangleError::NotFound(format!("The folder for {} could not be found on Google Drive", key)).into())
+}
 
-    let files = client.files().list_all(
-        "allDrives",  // corpora
-        "", // drive id
-        true,     // include_items_from_all_drives
-        "",       // include_permissions_for_view
-        false,    // include_team_drive_items
-        "",       // order_by
-        &query,   // query
-        "",       // spaces
-        true,     // supports_all_drives
-        false,    // supports_team_drives
-        "",       // team_drive_id
-    )
-    .await;
+async fn gd_create(
+    name: &str,
+    file_contents: &[u8],
+    parent_id: &str,
+    session: &DriveSession,
+) -> Result<google_drive::types::File, Error> {
+    session.call(|client| async move {
+        client.files().create_or_update(
+            "",
+            parent_id,
+            name,
+            "application/octet-stream", // Replace with the actual mime type
+            file_contents,
+        ).await
+    }).await
+}
 
-    let filesvec = files.unwrap().body;
+// The contents of the file at files_name, or None if there is nothing there
+pub(crate) async fn gd_get_file(
+    files_name: &str,
+    index: &DriveIndex,
+    session: &DriveSession,
+) -> Result<Option<Vec<u8>>, Error> {
+    let key = path_key(Path::new(files_name));
+
+    // A cached id can point at a file someone has since deleted, that only shows once the download fails
+    for _ in 0..2 {
+        let id = match gd_find(&key, index, session).await? {
+            Some(v) => v,
+            None => return Ok(None),
+        };
+
+        if index.is_folder(&key) {
+            return Err(EntangleError::Drive(format!("{} is a folder", files_name)).into());
+        }
+
+        let link = session.api_url(&format!("/drive/v3/files/{}?alt=media", id));
+
+        let response = session.send(|gds| {
+            reqwest::Client::new().get(link.clone()).header("Authorization", format!("Bearer {}", gds.token.access_token))
+        }).await?;
+
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            index.invalidate(&key);
+            continue;
+        }
 
-    if filesvec.is_empty() {
-        return;
-    }else{
-        let fl = filesvec.get(0).unwrap();
+        if !response.status().is_success() {
+            return Err(EntangleError::Drive(format!("Failed to download {}: {}", files_name, response.status())).into());
+        }
 
-        client.files().delete(&fl.id, true, true).await.unwrap();
+        let body = response.bytes().await.map_err(|e| EntangleError::Network(format!("Failed to download {}: {}", files_name, e)))?;
+        return Ok(Some(body.to_vec()));
     }
+
+    Ok(None)
 }
 
-pub(crate) async fn create_folder_gd(
-    folders: Vec<String>,
-    folder_id_glb: String,
-    client_glb: &Client,
-    tokens_glb: &AccessToken,
-) {
-    for file in &folders {
-        let client = client_glb.clone();
-        let folder_id = folder_id_glb.clone();
-        let mut folder_id_tmp: String = folder_id.to_string();
-        let file_path = file.clone();
-        let relative_path = Path::new(file);
-        let mut current_parent_id = folder_id_tmp.to_string();
-
-        for component in relative_path.components() {
-            if component == Component::RootDir {
-                continue;
-            }
+pub(crate) async fn gd_delete_file(
+    files_name: &str,
+    index: &DriveIndex,
+    session: &DriveSession,
+) -> Result<(), Error> {
+    let key = path_key(Path::new(files_name));
+
+    if let Some(id) = gd_find(&key, index, session).await? {
+        let id = &id;
+        let result = session.call(|client| async move {
+            client.files().delete(id, true, true).await
+        }).await;
+
+        match result {
+            // Already gone is as good as deleted
+            Err(e) if e.kind() != ErrorKind::NotFound => return Err(e),
+            _ => index.invalidate(&key),
+        }
+    }
+    Ok(())
+}
 
-            let component_str = component.as_os_str().to_str().unwrap();
+// The id of the folder at key, without creating anything. Lookups go through here, so reading or deleting a path
+// never leaves empty folders behind on the shared drive
+async fn gd_resolve_folder(
+    key: &str,
+    index: &DriveIndex,
+    session: &DriveSession,
+) -> Result<Option<String>, Error> {
+    let mut current_key = String::new();
+    let mut current_parent_id = index.folder_id().to_string();
+
+    for component in key.split('/').filter(|c| !c.is_empty()) {
+        current_key = if current_key.is_empty() { component.to_string() } else { format!("{}/{}", current_key, component) };
+
+        if let Some(id) = index.get(&current_key) {
+            current_parent_id = id;
+            continue;
+        }
 
-            let folder_mime_type = "application/vnd.google-apps.folder";
-            let mut file: google_drive::types::File = Default::default();
-            // Set the name,
-            file.name = component_str.to_string();
-            file.mime_type = folder_mime_type.to_string();
-            file.parents = vec![current_parent_id.clone()];
-            let res = client.files().create(false, "published", false, "en", true, true, false, &file).await.unwrap();
-            current_parent_id = res.body.id;
+        let query = format!(
+            "name = '{}' and mimeType = '{}' and '{}' in parents and trashed = false",
+            component, FOLDER_MIME_TYPE, current_parent_id
+        );
+
+        match gd_query(session, "", &query).await?.into_iter().next() {
+            Some(existing) => {
+                index.insert(current_key.clone(), existing.id.clone(), current_parent_id, true);
+                current_parent_id = existing.id;
+            }
+            None => return Ok(None),
         }
     }
+
+    Ok(Some(current_parent_id))
 }
 
-pub(crate) async fn delete_folder_gd(
-    name: String,
-    folder_id: String,
-    client_glb: &Client,
-    tokens_glb: &AccessToken,
-) {
-    let client: Client = client_glb.clone();
-    let mut folder_id_tmp: String = folder_id.to_string();
-    let file_path = name.clone();
-    let relative_path = Path::new(&name);
-    let mut current_parent_id = folder_id_tmp.to_string();
-
-    for component in relative_path.parent().unwrap_or(Path::new("")).components() {
+// Walk (and create where missing) the folder chain for a relative folder path, returning the id of the last folder.
+// Only for writes, lookups use gd_resolve_folder
+pub(crate) async fn gd_ensure_folder(
+    folder_path: &Path,
+    index: &DriveIndex,
+    session: &DriveSession,
+) -> Result<String, Error> {
+    let _creating = index.lock_folders().await;
+    let mut current_key = String::new();
+    let mut current_parent_id = index.folder_id().to_string();
+
+    for component in folder_path.components() {
         if component == Component::RootDir {
             continue;
         }
 
-        let component_str = component.as_os_str().to_str().unwrap();
+        let component_str = component.as_os_str().to_str()
+            .ok_or_else(|| EntangleError::Io(format!("{} is not a valid folder name", folder_path.display())))?;
+        current_key = if current_key.is_empty() { component_str.to_string() } else { format!("{}/{}", current_key, component_str) };
+
+        if let Some(id) = index.get(&current_key) {
+            current_parent_id = id;
+            continue;
+        }
 
-        let folder_mime_type = "application/vnd.google-apps.folder";
-        let mut file: google_drive::types::File = Default::default();
-        // Set the name,
-        file.name = component_str.to_string();
-        file.mime_type = folder_mime_type.to_string();
-        file.parents = vec![current_parent_id.clone()];
-    
         let mut query = format!(
-            "name = '{}' and mimeType = 'application/vnd.google-apps.folder'",
-            component_str
+            "name = '{}' and mimeType = '{}'",
+            component_str, FOLDER_MIME_TYPE
         );
         query = format!("{} and '{}' in parents and trashed = false", query, current_parent_id.clone());
-    
+
         // Check if the folder exists.
-        let resp = client.files()
-            .list_all(
-                "allDrives",  // corpora
-                "", // drive id
-                true,     // include_items_from_all_drives
-                "",       // include_permissions_for_view
-                false,    // include_team_drive_items
-                "",       // order_by
-                &query,   // query
-                "",       // spaces
-                true,     // supports_all_drives
-                false,    // supports_team_drives
-                "",       // team_drive_id
-            )
-            .await.unwrap();
-    
-        if !resp.body.is_empty() {
-            current_parent_id = resp.body.get(0).unwrap().id.clone();
+        let resp = gd_query(session, "", &query).await?;
+
+        let id = if let Some(existing) = resp.first() {
+            existing.id.clone()
         }else{
-            return;
+            let mut file: google_drive::types::File = Default::default();
+            // Set the name,
+            file.name = component_str.to_string();
+            file.mime_type = FOLDER_MIME_TYPE.to_string();
+            file.parents = vec![current_parent_id.clone()];
+
+            let file = &file;
+            let res = session.call(|client| async move {
+                client.files().create(false, "published", false, "en", true, true, false, file).await
+            }).await?;
+            res.id
+        };
+
+        index.insert(current_key.clone(), id.clone(), current_parent_id, true);
+        current_parent_id = id;
+    }
+
+    Ok(current_parent_id)
+}
+
+// Store a file in the remote object store under its sha256. Objects never change, so an existing one is not re-uploaded.
+// path is the project file the object is the contents of, which is what the upload progress shows
+pub(crate) async fn gd_put_object(
+    file: &Path,
+    path: &str,
+    sha256: &str,
+    index: &DriveIndex,
+    uploads: &UploadSessions,
+    session: &DriveSession,
+    on_progress: Option<&OnUploadProgress>,
+) -> Result<(), Error> {
+    let key = format!("{}/{}", OBJECTS_DIR, sha256);
+    if gd_find(&key, index, session).await?.is_some() {
+        return Ok(());
+    }
+
+    gd_upload_file(Path::new(&key), path, file, index, uploads, session, on_progress).await
+}
+
+// Make the file at relative_path a copy of the object for sha256. Drive copies it on its side, so nothing is uploaded
+// again. An existing file there is replaced by the copy
+pub(crate) async fn gd_copy_object(
+    sha256: &str,
+    relative_path: &Path,
+    index: &DriveIndex,
+    session: &DriveSession,
+) -> Result<(), Error> {
+    let object_key = format!("{}/{}", OBJECTS_DIR, sha256);
+    let object_id = gd_find(&object_key, index, session).await?
+        .ok_or_else(|| EntangleError::NotFound(format!("Missing object {}", sha256)))?;
+
+    let key = path_key(relative_path);
+    let name = file_name(relative_path)?;
+    let existing = gd_find(&key, index, session).await?;
+    let parent_id = gd_ensure_folder(Path::new(parent_key(&key)), index, session).await?;
+
+    let link = session.api_url(&format!("/drive/v3/files/{}/copy?supportsAllDrives=true", object_id));
+    let metadata = json!({ "name": name, "parents": [parent_id] });
+    let response = session.send(|gds| {
+        reqwest::Client::new().post(link.clone()).header("Authorization", format!("Bearer {}", gds.token.access_token)).json(&metadata)
+    }).await?;
+
+    if response.status() == reqwest::StatusCode::NOT_FOUND {
+        index.invalidate(&object_key);
+        return Err(EntangleError::NotFound(format!("Missing object {}", sha256)).into());
+    }
+    if !response.status().is_success() {
+        return Err(EntangleError::Drive(format!("Failed to copy {} into place: {}", key, response.status())).into());
+    }
+    let copy: CopiedFile = response.json().await.map_err(|e| EntangleError::Drive(format!("Unexpected response copying {}: {}", key, e)))?;
+
+    // The old version goes once the new one is in place, so there is never a moment without the file
+    if let Some(old_id) = existing {
+        let old_id = &old_id;
+        let result = session.call(|client| async move {
+            client.files().delete(old_id, true, true).await
+        }).await;
+        if let Err(e) = result {
+            if e.kind() != ErrorKind::NotFound {
+                return Err(e);
+            }
+        }
+    }
+    index.insert(key, copy.id, parent_id, false);
+    Ok(())
+}
+
+#[derive(Deserialize)]
+struct CopiedFile {
+    id: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct StoredFile {
+    #[serde(default)]
+    size: String,
+    sha256_checksum: Option<String>,
+}
+
+// Whether Drive holds the whole object for sha256. Drive reports the size and checksum of what it stored, so nothing
+// has to be downloaded again to check
+pub(crate) async fn gd_verify_object(
+    sha256: &str,
+    size: u64,
+    index: &DriveIndex,
+    session: &DriveSession,
+) -> Result<bool, Error> {
+    let key = format!("{}/{}", OBJECTS_DIR, sha256);
+    let id = match gd_find(&key, index, session).await? {
+        Some(v) => v,
+        None => return Ok(false),
+    };
+
+    let link = session.api_url(&format!("/drive/v3/files/{}?fields=size,sha256Checksum&supportsAllDrives=true", id));
+    let response = session.send(|gds| {
+        reqwest::Client::new().get(link.clone()).header("Authorization", format!("Bearer {}", gds.token.access_token))
+    }).await?;
+
+    if response.status() == reqwest::StatusCode::NOT_FOUND {
+        index.invalidate(&key);
+        return Ok(false);
+    }
+    if !response.status().is_success() {
+        return Err(EntangleError::Drive(format!("Failed to check object {}: {}", sha256, response.status())).into());
+    }
+
+    let stored: StoredFile = response.json().await.map_err(|e| EntangleError::Drive(format!("Unexpected response checking object {}: {}", sha256, e)))?;
+    Ok(stored.size.parse::<u64>().ok() == Some(size) && stored.sha256_checksum.map_or(true, |v| v == sha256))
+}
+
+pub(crate) async fn gd_get_object(
+    sha256: &str,
+    index: &DriveIndex,
+    session: &DriveSession,
+) -> Result<Option<Vec<u8>>, Error> {
+    gd_get_file(&format!("{}/{}", OBJECTS_DIR, sha256), index, session).await
+}
+
+// Every file in the project folder, as paths relative to it. Entangle's own bookkeeping folder is left out of the
+// result, but everything, bookkeeping included, goes into a fresh index
+pub(crate) async fn gd_list(
+    index: &DriveIndex,
+    session: &DriveSession,
+) -> Result<Vec<String>, Error> {
+    let mut result = Vec::new();
+    let mut ids = GDriveIDs::default();
+    let mut pending = vec![(String::new(), index.folder_id().to_string())];
+
+    while let Some((prefix, parent_id)) = pending.pop() {
+        let query = format!("'{}' in parents and trashed = false", parent_id);
+
+        for fl in gd_query(session, "", &query).await? {
+            let path = if prefix.is_empty() { fl.name.clone() } else { format!("{}/{}", prefix, fl.name) };
+            let is_folder = fl.mime_type == FOLDER_MIME_TYPE;
+            ids.insert(path.clone(), fl.id.clone(), parent_id.clone(), is_folder);
+
+            if is_folder {
+                pending.push((path, fl.id));
+            }else if path.split('/').next() != Some(META_DIR) {
+                result.push(path);
+            }
+        }
+    }
+
+    index.replace(ids);
+    Ok(result)
+}
+
+// A folder on Google Drive, addressed by its id
+pub(crate) struct GoogleDriveRemote {
+    index: DriveIndex,
+    uploads: UploadSessions,
+    project: String,
+    session: DriveSession,
+    on_upload: Option<Box<OnUploadProgress>>,
+}
+
+impl GoogleDriveRemote {
+    pub fn new(session: &DriveSession, folder_id: &str, project: &str) -> GoogleDriveRemote {
+        GoogleDriveRemote {
+            index: DriveIndex::load(folder_id),
+            uploads: UploadSessions::load(folder_id),
+            project: project.to_string(),
+            session: session.clone(),
+            on_upload: None,
         }
     }
-    client_glb.files().delete_by_name(&folder_id, &current_parent_id, relative_path.file_name().unwrap().to_str().unwrap());
-}
\ No newline at end of file
+
+    // Report how far along each file upload is
+    pub fn with_upload_progress(mut self, on_upload: impl Fn(UploadProgress) + Send + Sync + 'static) -> GoogleDriveRemote {
+        self.on_upload = Some(Box::new(on_upload));
+        self
+    }
+}
+
+#[async_trait]
+impl Remote for GoogleDriveRemote {
+    async fn read_manifest(&self) -> Result<SyncInfo, Error> {
+        match gd_get_file(&format!("{}.sync", self.project), &self.index, &self.session).await? {
+            Some(bytes) => Ok(serde_json::from_slice(&bytes)?),
+            None => Err(EntangleError::NotFound(format!("No {}.sync on the remote", self.project)).into()),
+        }
+    }
+
+    // A plain in-place update of the .sync file. Drive can't replace it only if it's unchanged, so commit checks the
+    // revision right before calling this
+    async fn write_manifest(&self, manifest: &SyncInfo) -> Result<(), Error> {
+        let contents = serde_json::to_vec_pretty(manifest)?;
+        gd_upload(Path::new(&format!("{}.sync", self.project)), contents, &self.index, &self.session).await
+    }
+
+    async fn read_revision(&self, id: &str) -> Result<Revision, Error> {
+        match gd_get_file(&revision_path(id), &self.index, &self.session).await? {
+            Some(bytes) => Ok(parse_revision(&bytes)?),
+            None => Err(EntangleError::NotFound(format!("Revision {} is not on the remote", id)).into()),
+        }
+    }
+
+    // Revisions never change, so one already there is left as it is
+    async fn write_revision(&self, revision: &Revision) -> Result<(), Error> {
+        let path = revision_path(&revision.id);
+        if gd_find(&path, &self.index, &self.session).await?.is_some() {
+            return Ok(());
+        }
+        let contents = serde_json::to_vec_pretty(revision)?;
+        gd_upload(Path::new(&path), contents, &self.index, &self.session).await
+    }
+
+    async fn read_history(&self) -> Result<History, Error> {
+        let bytes = gd_get_file(&format!("{}.history", self.project), &self.index, &self.session).await?.unwrap_or_default();
+        Ok(parse_history(&bytes)?)
+    }
+
+    async fn read_locks(&self) -> Result<Locks, Error> {
+        let bytes = gd_get_file(&format!("{}.locks", self.project), &self.index, &self.session).await?.unwrap_or_default();
+        Ok(parse_locks(&bytes)?)
+    }
+
+    async fn write_locks(&self, locks: &Locks) -> Result<(), Error> {
+        let contents = serde_json::to_vec_pretty(locks)?;
+        gd_upload(Path::new(&format!("{}.locks", self.project)), contents, &self.index, &self.session).await
+    }
+
+    async fn get_blob(&self, path: &str) -> Result<Vec<u8>, Error> {
+        match gd_get_file(path, &self.index, &self.session).await? {
+            Some(v) => Ok(v),
+            None => Err(EntangleError::NotFound(format!("{} is not on the remote", path)).into()),
+        }
+    }
+
+    async fn copy_object(&self, sha256: &str, path: &str) -> Result<(), Error> {
+        gd_copy_object(sha256, Path::new(path), &self.index, &self.session).await
+    }
+
+    async fn create_folder(&self, path: &str) -> Result<(), Error> {
+        gd_ensure_folder(Path::new(path), &self.index, &self.session).await?;
+        Ok(())
+    }
+
+    async fn delete(&self, path: &str) -> Result<(), Error> {
+        gd_delete_file(path, &self.index, &self.session).await
+    }
+
+    async fn list(&self) -> Result<Vec<String>, Error> {
+        let own = [format!("{}.sync", self.project), format!("{}.history", self.project), format!("{}.locks", self.project)];
+        Ok(gd_list(&self.index, &self.session).await?
+            .into_iter()
+            .filter(|path| !own.contains(path))
+            .collect())
+    }
+
+    async fn get_object(&self, sha256: &str) -> Result<Vec<u8>, Error> {
+        match gd_get_object(sha256, &self.index, &self.session).await? {
+            Some(v) => Ok(v),
+            None => Err(EntangleError::NotFound(format!("Missing object {}", sha256)).into()),
+        }
+    }
+
+    async fn put_object(&self, sha256: &str, path: &str, local_file: &Path) -> Result<(), Error> {
+        gd_put_object(local_file, path, sha256, &self.index, &self.uploads, &self.session, self.on_upload.as_deref()).await
+    }
+
+    async fn verify_object(&self, sha256: &str, size: u64) -> Result<bool, Error> {
+        gd_verify_object(sha256, size, &self.index, &self.session).await
+    }
+
+    fn account(&self) -> Option<String> {
+        self.session.email()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use crate::{SyncFile, engine};
+    use crate::gdupload::UPLOAD_CHUNK_SIZE;
+    use crate::mockdrive::{MockDrive, ROOT_ID};
+    use crate::status::FileStatus;
+    use crate::testproject::{PROJECT, TestDir, remote_file, selected, sha256_of, synced_file};
+
+    // A remote on the mock drive whose index isn't saved between tests
+    fn mock_remote(drive: &MockDrive) -> GoogleDriveRemote {
+        GoogleDriveRemote {
+            index: DriveIndex::in_memory(ROOT_ID),
+            uploads: UploadSessions::in_memory(),
+            project: PROJECT.to_string(),
+            session: drive.session(),
+            on_upload: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn reading_from_a_missing_folder_creates_nothing() {
+        let drive = MockDrive::start().await;
+        let remote = mock_remote(&drive);
+
+        let result = remote.get_blob("parts/brackets/bracket.sldprt").await;
+
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::NotFound);
+        assert_eq!(drive.creates(), Vec::<String>::new());
+        assert!(drive.folder_names().is_empty());
+    }
+
+    #[tokio::test]
+    async fn deleting_from_a_missing_folder_creates_nothing() {
+        let drive = MockDrive::start().await;
+        let remote = mock_remote(&drive);
+
+        remote.delete("parts/old/bracket.sldprt").await.unwrap();
+
+        assert_eq!(drive.creates(), Vec::<String>::new());
+        assert!(drive.folder_names().is_empty());
+    }
+
+    #[tokio::test]
+    async fn a_trashed_file_is_as_good_as_gone() {
+        let drive = MockDrive::start().await;
+        let parts = drive.add_folder(ROOT_ID, "parts");
+        let old = drive.add_file(&parts, "bracket.sldprt", b"bracket");
+        let remote = mock_remote(&drive);
+        assert_eq!(remote.get_blob("parts/bracket.sldprt").await.unwrap(), b"bracket");
+
+        // Someone trashes the indexed file and puts a new one in its place
+        drive.trash(&old);
+        drive.add_file(&parts, "bracket.sldprt", b"bracket v2");
+
+        assert_eq!(remote.get_blob("parts/bracket.sldprt").await.unwrap(), b"bracket v2");
+    }
+
+    #[tokio::test]
+    async fn pull_creates_nothing() {
+        let drive = MockDrive::start().await;
+        let parts = drive.add_folder(ROOT_ID, "parts");
+        drive.add_file(&parts, "bracket.sldprt", b"bracket");
+
+        // The manifest also lists a file whose folder has gone missing from the drive
+        let manifest = SyncInfo {
+            files: vec![synced_file("parts/bracket.sldprt", b"bracket"), remote_file("assemblies/top.sldasm")],
+            folders: vec!["parts".to_string(), "assemblies".to_string()],
+            ..Default::default()
+        };
+        drive.add_file(ROOT_ID, "test.sync", &serde_json::to_vec(&manifest).unwrap());
+
+        let project = TestDir::project(&SyncInfo::default());
+        let remote = mock_remote(&drive);
+        let result = engine::pull(&remote, &project.path, PROJECT, &[selected("parts/bracket.sldprt", FileStatus::RemoteAdded)]).await;
+        let missing = engine::pull(&remote, &project.path, PROJECT, &[selected("assemblies/top.sldasm", FileStatus::RemoteAdded)]).await;
+
+        result.unwrap();
+        assert_eq!(project.read("parts/bracket.sldprt").unwrap(), b"bracket");
+        // A file that isn't there is an error, not an empty file
+        assert_eq!(missing.unwrap_err().kind(), ErrorKind::NotFound);
+        assert_eq!(project.read("assemblies/top.sldasm"), None);
+        assert_eq!(drive.creates(), Vec::<String>::new());
+        assert_eq!(drive.folder_names(), vec!["parts".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn pull_refuses_a_file_that_does_not_match_the_manifest() {
+        let drive = MockDrive::start().await;
+        let parts = drive.add_folder(ROOT_ID, "parts");
+        drive.add_file(&parts, "bracket.sldprt", b"cut off");
+
+        let manifest = SyncInfo { files: vec![synced_file("parts/bracket.sldprt", b"bracket v2")], ..Default::default() };
+        drive.add_file(ROOT_ID, "test.sync", &serde_json::to_vec(&manifest).unwrap());
+
+        let project = TestDir::project(&SyncInfo::default());
+        project.write("parts/bracket.sldprt", b"bracket v1");
+
+        let remote = mock_remote(&drive);
+        let result = engine::pull(&remote, &project.path, PROJECT, &[selected("parts/bracket.sldprt", FileStatus::RemoteModified)]).await;
+
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::InvalidData);
+        assert_eq!(project.read("parts/bracket.sldprt").unwrap(), b"bracket v1");
+    }
+
+    #[tokio::test]
+    async fn interrupted_upload_resumes_where_it_stopped() {
+        let drive = MockDrive::start().await;
+        let progress = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let reported = progress.clone();
+        let remote = mock_remote(&drive).with_upload_progress(move |p| reported.lock().unwrap().push((p.path, p.bytes_done)));
+
+        let size = UPLOAD_CHUNK_SIZE * 2 + 1000;
+        let contents: Vec<u8> = (0..size).map(|i| (i % 251) as u8).collect();
+        let project = TestDir::new();
+        project.write("top.sldasm", &contents);
+        let local = project.path.join("top.sldasm");
+
+        let sha256 = sha256_of(&contents);
+
+        // The connection drops after the first chunk and stays down
+        drive.fail_chunks_from(Some(UPLOAD_CHUNK_SIZE));
+        let interrupted = remote.put_object(&sha256, "assemblies/top.sldasm", &local).await;
+
+        drive.fail_chunks_from(None);
+        let resumed = remote.put_object(&sha256, "assemblies/top.sldasm", &local).await;
+
+        assert!(interrupted.is_err());
+        resumed.unwrap();
+        assert_eq!(drive.contents(&sha256).unwrap(), contents);
+        // One session for both attempts, and nothing sent twice
+        assert_eq!(drive.requests().iter().filter(|r| *r == "POST /upload/drive/v3/files").count(), 1);
+        assert_eq!(drive.chunk_starts(), vec![0, UPLOAD_CHUNK_SIZE, UPLOAD_CHUNK_SIZE * 2]);
+        // Shown as the file being committed, not the object it is stored as
+        assert_eq!(progress.lock().unwrap().last(), Some(&("assemblies/top.sldasm".to_string(), size)));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn committed_files_are_only_uploaded_once() {
+        let drive = MockDrive::start().await;
+        drive.add_file(ROOT_ID, "test.sync", &serde_json::to_vec(&SyncInfo::default()).unwrap());
+        let project = TestDir::project(&SyncInfo::default());
+        project.write("parts/bracket.sldprt", b"bracket");
+        let remote = mock_remote(&drive);
+
+        let file = selected("parts/bracket.sldprt", FileStatus::LocalAdded);
+        engine::commit(&remote, &project.path, PROJECT, &[file], None, "Add bracket".to_string(), Default::default(), false, 1, &|_| {}).await.unwrap();
+
+        // The object is sent, the browsable file is copied from it on the drive
+        assert_eq!(drive.requests().iter().filter(|r| *r == "POST /upload/drive/v3/files").count(), 1);
+        assert_eq!(drive.contents(&sha256_of(b"bracket")).unwrap(), b"bracket");
+        assert_eq!(drive.contents("bracket.sldprt").unwrap(), b"bracket");
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn failed_commit_leaves_the_manifest_alone() {
+        let drive = MockDrive::start().await;
+        let manifest = serde_json::to_vec(&SyncInfo::default()).unwrap();
+        drive.add_file(ROOT_ID, "test.sync", &manifest);
+
+        let project = TestDir::project(&SyncInfo::default());
+        project.write("parts/bracket.sldprt", b"bracket");
+
+        // Nothing gets through
+        drive.fail_chunks_from(Some(0));
+        let remote = mock_remote(&drive);
+        let file = selected("parts/bracket.sldprt", FileStatus::LocalAdded);
+        let result = engine::commit(&remote, &project.path, PROJECT, &[file], None, "Add bracket".to_string(), Default::default(), false, 1, &|_| {}).await;
+
+        assert!(result.is_err());
+        assert_eq!(drive.contents("test.sync").unwrap(), manifest);
+        assert!(!drive.folder_names().contains(&"revisions".to_string()));
+        assert_eq!(drive.contents("bracket.sldprt"), None);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn commit_over_a_newer_remote_version_is_refused() {
+        let drive = MockDrive::start().await;
+        let remote_version = SyncFile { sha256: "theirs".to_string(), ..remote_file("parts/bracket.sldprt") };
+        let manifest = SyncInfo { files: vec![remote_version], ..Default::default() };
+        drive.add_file(ROOT_ID, "test.sync", &serde_json::to_vec(&manifest).unwrap());
+
+        // Last pulled before someone else committed their version
+        let baseline = SyncInfo { files: vec![SyncFile { sha256: "base".to_string(), ..remote_file("parts/bracket.sldprt") }], ..Default::default() };
+        let project = TestDir::project(&baseline);
+        project.write("parts/bracket.sldprt", b"mine");
+
+        let remote = mock_remote(&drive);
+        let file = selected("parts/bracket.sldprt", FileStatus::LocalModified);
+        let result = engine::commit(&remote, &project.path, PROJECT, &[file], manifest.revision.clone(), "Edit bracket".to_string(), Default::default(), false, 1, &|_| {}).await;
+
+        assert!(EntangleError::from(result.unwrap_err()).to_string().contains("pull first"));
+        assert_eq!(drive.creates(), Vec::<String>::new());
+    }
+}
@@ -0,0 +1,273 @@
+use std::{collections::HashMap, fs::{self, File}, io::{Error, Read, Seek, SeekFrom}, path::{Path, PathBuf}, sync::Mutex, time::{Duration, SystemTime, UNIX_EPOCH}};
+
+use reqwest::{Method, StatusCode, header};
+use serde::{Serialize, Deserialize};
+
+use crate::error::EntangleError;
+use crate::gdhttp::DriveSession;
+
+// Drive takes resumable uploads in multiples of 256 KiB, 8 MiB keeps a multi-GB assembly to a few hundred requests
+pub(crate) const UPLOAD_CHUNK_SIZE: u64 = 32 * 256 * 1024;
+
+// A chunk that fails is retried this many times, after asking Drive how much of the file it already has
+const UPLOAD_RETRIES: u32 = 3;
+
+// Drive forgets an upload session after a week, older ones aren't worth asking about
+const SESSION_LIFETIME: Duration = Duration::from_secs(6 * 24 * 60 * 60);
+
+// How far along the upload of one file is, sent to the frontend as upload-progress events
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct UploadProgress {
+    pub path: String,
+    pub bytes_done: u64,
+    pub bytes_total: u64,
+}
+
+pub(crate) type OnUploadProgress = dyn Fn(UploadProgress) + Send + Sync;
+
+// An upload session Drive has open for a file. size and modified tell whether the local file is still the one that
+// was being sent
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct SavedUpload {
+    pub uri: String,
+    pub size: u64,
+    pub modified: u64,
+    // The folder a new file is going into, None when an existing file is being replaced
+    pub parent_id: Option<String>,
+    started: u64,
+}
+
+impl SavedUpload {
+    pub fn new(uri: String, size: u64, modified: u64, parent_id: Option<String>) -> SavedUpload {
+        SavedUpload { uri, size, modified, parent_id, started: unix_secs(SystemTime::now()) }
+    }
+}
+
+// Uploads that haven't finished yet, by Drive path. Saved as soon as they change, so a push cut off by a crash or the
+// app closing carries on from the last chunk Drive got instead of from the start
+pub(crate) struct UploadSessions {
+    path: Option<PathBuf>,
+    saved: Mutex<HashMap<String, SavedUpload>>,
+}
+
+impl UploadSessions {
+    pub fn load(folder_id: &str) -> UploadSessions {
+        let path = dirs::cache_dir().map(|v| v.join("Entangle").join("uploads").join(format!("{}.json", folder_id)));
+        let saved = path.as_ref()
+            .and_then(|pth| fs::read(pth).ok())
+            .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+            .unwrap_or_default();
+
+        UploadSessions { path, saved: Mutex::new(saved) }
+    }
+
+    // Sessions that are only kept as long as this is
+    #[cfg(test)]
+    pub fn in_memory() -> UploadSessions {
+        UploadSessions { path: None, saved: Mutex::new(HashMap::new()) }
+    }
+
+    // The session for key, if it was sending the same version of the file and Drive should still have it
+    pub fn get(&self, key: &str, size: u64, modified: u64) -> Option<SavedUpload> {
+        let now = unix_secs(SystemTime::now());
+        self.saved.lock().unwrap().get(key)
+            .filter(|s| s.size == size && s.modified == modified)
+            .filter(|s| now.saturating_sub(s.started) < SESSION_LIFETIME.as_secs())
+            .cloned()
+    }
+
+    pub fn insert(&self, key: &str, upload: SavedUpload) {
+        let mut saved = self.saved.lock().unwrap();
+        saved.insert(key.to_string(), upload);
+        self.save(&saved);
+    }
+
+    pub fn remove(&self, key: &str) {
+        let mut saved = self.saved.lock().unwrap();
+        if saved.remove(key).is_some() {
+            self.save(&saved);
+        }
+    }
+
+    fn save(&self, saved: &HashMap<String, SavedUpload>) {
+        let pth = match &self.path {
+            Some(v) => v,
+            None => return,
+        };
+
+        let result = fs::create_dir_all(pth.parent().unwrap())
+            .and_then(|_| fs::write(pth, serde_json::to_vec(saved)?));
+        if let Err(e) = result {
+            eprintln!("Failed to save upload sessions: {}", e);
+        }
+    }
+}
+
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+// The size and modification time of a local file, what a saved session is matched against
+pub(crate) fn file_version(local_file: &Path) -> Result<(u64, u64), Error> {
+    let meta = fs::metadata(local_file)?;
+    Ok((meta.len(), meta.modified().map(unix_secs).unwrap_or(0)))
+}
+
+// Drive answers an unfinished chunk with 308 Resume Incomplete, which must not be followed like a redirect
+fn upload_client() -> reqwest::Client {
+    reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap_or_default()
+}
+
+#[derive(Deserialize)]
+struct Uploaded {
+    id: String,
+}
+
+// Where an upload session stands
+pub(crate) enum SessionState {
+    // Drive has this many bytes of the file
+    Offset(u64),
+    // Drive has all of it, as the file with this id
+    Done(String),
+    // Drive no longer knows the session, the upload has to start over
+    Expired,
+}
+
+// Open a resumable upload session at url, POST to create a file and PATCH to replace one. Returns the session URI, or
+// None if Drive says the file or folder it targets doesn't exist
+pub(crate) async fn start_session(
+    method: Method,
+    url: &str,
+    metadata: &serde_json::Value,
+    size: u64,
+    session: &DriveSession,
+) -> Result<Option<String>, Error> {
+    let client = upload_client();
+    let response = session.send(|gds| {
+        client.request(method.clone(), url)
+            .header("Authorization", format!("Bearer {}", gds.token.access_token))
+            .header("X-Upload-Content-Type", "application/octet-stream")
+            .header("X-Upload-Content-Length", size.to_string())
+            .json(metadata)
+    }).await?;
+
+    if response.status() == StatusCode::NOT_FOUND {
+        return Ok(None);
+    }
+    if !response.status().is_success() {
+        return Err(EntangleError::Drive(format!("Failed to start upload: {}", response.status())).into());
+    }
+
+    match response.headers().get(header::LOCATION).and_then(|v| v.to_str().ok()) {
+        Some(uri) => Ok(Some(uri.to_string())),
+        None => Err(EntangleError::Drive("Google Drive did not return an upload session".to_string()).into()),
+    }
+}
+
+// The bytes Drive has received so far, from the Range header of a 308. No header means none yet
+fn received(response: &reqwest::Response) -> u64 {
+    response.headers().get(header::RANGE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.rsplit('-').next())
+        .and_then(|v| v.parse::<u64>().ok())
+        .map(|last| last + 1)
+        .unwrap_or(0)
+}
+
+async fn finished(response: reqwest::Response) -> Result<String, Error> {
+    let uploaded: Uploaded = response.json().await.map_err(|e| EntangleError::Drive(format!("Unexpected upload response: {}", e)))?;
+    Ok(uploaded.id)
+}
+
+// Ask Drive how much of a session's file it has
+pub(crate) async fn session_state(uri: &str, size: u64, session: &DriveSession) -> Result<SessionState, Error> {
+    let client = upload_client();
+    let response = session.send(|gds| {
+        client.put(uri)
+            .header("Authorization", format!("Bearer {}", gds.token.access_token))
+            .header(header::CONTENT_RANGE, format!("bytes */{}", size))
+            .body(Vec::new())
+    }).await?;
+
+    match response.status() {
+        StatusCode::PERMANENT_REDIRECT => Ok(SessionState::Offset(received(&response))),
+        StatusCode::NOT_FOUND | StatusCode::GONE => Ok(SessionState::Expired),
+        s if s.is_success() => Ok(SessionState::Done(finished(response).await?)),
+        s => Err(EntangleError::Drive(format!("Failed to check upload: {}", s)).into()),
+    }
+}
+
+// Send local_file to an upload session a chunk at a time, starting at offset. A chunk that fails to go through is
+// retried from wherever Drive says it got to. Returns the id of the uploaded file, or NotFound if the session expired
+pub(crate) async fn send_chunks(
+    uri: &str,
+    local_file: &Path,
+    size: u64,
+    mut offset: u64,
+    session: &DriveSession,
+    on_progress: &(dyn Fn(u64) + Send + Sync),
+) -> Result<String, Error> {
+    let client = upload_client();
+    let mut file = File::open(local_file)?;
+    let mut failures = 0;
+
+    loop {
+        let end = (offset + UPLOAD_CHUNK_SIZE).min(size);
+        let mut chunk = vec![0; (end - offset) as usize];
+        file.seek(SeekFrom::Start(offset))?;
+        file.read_exact(&mut chunk)?;
+
+        let range = if size == 0 { "bytes */0".to_string() } else { format!("bytes {}-{}/{}", offset, end - 1, size) };
+        let result = session.send(|gds| {
+            client.put(uri)
+                .header("Authorization", format!("Bearer {}", gds.token.access_token))
+                .header(header::CONTENT_RANGE, range.clone())
+                .body(chunk.clone())
+        }).await;
+
+        let error = match result {
+            Ok(response) if response.status().is_success() => {
+                on_progress(size);
+                return finished(response).await;
+            }
+            Ok(response) if response.status() == StatusCode::PERMANENT_REDIRECT => {
+                offset = received(&response);
+                failures = 0;
+                on_progress(offset);
+                continue;
+            }
+            Ok(response) if response.status() == StatusCode::NOT_FOUND || response.status() == StatusCode::GONE => {
+                return Err(EntangleError::NotFound("The upload session has expired".to_string()).into());
+            }
+            Ok(response) if response.status().is_server_error() => {
+                EntangleError::Drive(format!("Failed to upload {}: {}", local_file.display(), response.status())).into()
+            }
+            Ok(response) => {
+                return Err(EntangleError::Drive(format!("Failed to upload {}: {}", local_file.display(), response.status())).into());
+            }
+            // Dropped connections and the like
+            Err(e) => e,
+        };
+
+        failures += 1;
+        if failures > UPLOAD_RETRIES {
+            return Err(error);
+        }
+        eprintln!("Upload of {} interrupted, retrying: {}", local_file.display(), error);
+        tokio::time::sleep(Duration::from_millis(500 * failures as u64)).await;
+
+        match session_state(uri, size, session).await {
+            Ok(SessionState::Offset(v)) => offset = v,
+            Ok(SessionState::Done(id)) => {
+                on_progress(size);
+                return Ok(id);
+            }
+            Ok(SessionState::Expired) => return Err(EntangleError::NotFound("The upload session has expired".to_string()).into()),
+            // Still can't reach Drive, try the same chunk again
+            Err(_) => {}
+        }
+    }
+}
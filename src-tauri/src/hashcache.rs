@@ -0,0 +1,134 @@
+use std::{path::{Path, PathBuf}, fs::{self, Metadata}, collections::HashMap, time::UNIX_EPOCH};
+
+use serde::{Serialize, Deserialize};
+
+use crate::objects::META_DIR;
+
+// Enough about a file to tell that it hasn't been touched since it was last hashed
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct Fingerprint {
+    size: u64,
+    mtime: u128,
+    inode: u64,
+}
+
+impl Fingerprint {
+    pub fn of(metadata: &Metadata) -> Fingerprint {
+        Fingerprint {
+            size: metadata.len(),
+            mtime: metadata.modified()
+                .ok()
+                .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                .map(|d| d.as_nanos())
+                .unwrap_or(0),
+            inode: inode(metadata),
+        }
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+}
+
+#[cfg(unix)]
+fn inode(metadata: &Metadata) -> u64 {
+    use std::os::unix::fs::MetadataExt;
+    metadata.ino()
+}
+
+// File ids aren't exposed on stable for Windows, size and mtime have to do there
+#[cfg(not(unix))]
+fn inode(_metadata: &Metadata) -> u64 {
+    0
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CacheEntry {
+    #[serde(flatten)]
+    fingerprint: Fingerprint,
+    sha256: String,
+}
+
+// sha256 of every file in a project as of the last time it was hashed, keyed by relative path.
+// Stored in the project's .entangle folder so it never gets synced
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub(crate) struct HashCache {
+    entries: HashMap<String, CacheEntry>,
+}
+
+fn cache_path(root: &Path) -> PathBuf {
+    root.join(META_DIR).join("hashcache.json")
+}
+
+impl HashCache {
+    // A missing or unreadable cache just means everything gets hashed again
+    pub fn load(root: &Path) -> HashCache {
+        fs::read(cache_path(root))
+            .ok()
+            .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self, root: &Path) -> Result<(), std::io::Error> {
+        fs::create_dir_all(root.join(META_DIR))?;
+        fs::write(cache_path(root), serde_json::to_vec(self)?)
+    }
+
+    pub fn lookup(&self, path: &str, fingerprint: &Fingerprint) -> Option<String> {
+        self.entries.get(path)
+            .filter(|entry| entry.fingerprint == *fingerprint)
+            .map(|entry| entry.sha256.clone())
+    }
+
+    pub fn insert(&mut self, path: String, fingerprint: Fingerprint, sha256: String) {
+        self.entries.insert(path, CacheEntry { fingerprint, sha256 });
+    }
+}
+
+// Forget every cached hash, so the next listing hashes the whole project from scratch
+pub(crate) fn clear_cache(root: &Path) -> Result<(), std::io::Error> {
+    let path = cache_path(root);
+    if path.exists() {
+        fs::remove_file(path)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testproject::TestDir;
+
+    fn fingerprint(project: &TestDir, path: &str) -> Fingerprint {
+        Fingerprint::of(&fs::metadata(project.path.join(path)).unwrap())
+    }
+
+    #[test]
+    fn hashes_are_only_reused_for_untouched_files() {
+        let project = TestDir::new();
+        project.write("bracket.sldprt", b"bracket");
+        let mut cache = HashCache::default();
+        cache.insert("bracket.sldprt".to_string(), fingerprint(&project, "bracket.sldprt"), "abc".to_string());
+
+        assert_eq!(cache.lookup("bracket.sldprt", &fingerprint(&project, "bracket.sldprt")), Some("abc".to_string()));
+        assert_eq!(cache.lookup("plate.sldprt", &fingerprint(&project, "bracket.sldprt")), None);
+
+        project.write("bracket.sldprt", b"bracket v2");
+        assert_eq!(cache.lookup("bracket.sldprt", &fingerprint(&project, "bracket.sldprt")), None);
+    }
+
+    #[test]
+    fn the_cache_survives_a_save_until_cleared() {
+        let project = TestDir::new();
+        project.write("bracket.sldprt", b"bracket");
+        let mut cache = HashCache::default();
+        cache.insert("bracket.sldprt".to_string(), fingerprint(&project, "bracket.sldprt"), "abc".to_string());
+        cache.save(&project.path).unwrap();
+
+        let loaded = HashCache::load(&project.path);
+        assert_eq!(loaded.lookup("bracket.sldprt", &fingerprint(&project, "bracket.sldprt")), Some("abc".to_string()));
+
+        clear_cache(&project.path).unwrap();
+        assert_eq!(HashCache::load(&project.path).lookup("bracket.sldprt", &fingerprint(&project, "bracket.sldprt")), None);
+    }
+}
@@ -0,0 +1,185 @@
+use std::{path::Path, time::{UNIX_EPOCH, SystemTime}};
+
+use serde::{Serialize, Deserialize};
+use sha2::{Sha256, Digest};
+
+use crate::{SyncFile, SyncInfo, write_atomic};
+
+// A single immutable snapshot of a project, linked to the revision it was made on top of
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct Revision {
+    pub id: String,
+    pub parent: Option<String>,
+    pub author: String,
+    pub email: String,
+    // The Google account a Drive commit was made with
+    #[serde(default)]
+    pub account: Option<String>,
+    pub timestamp: u128,
+    pub msg: String,
+    pub files: Vec<SyncFile>,
+}
+
+// Who a commit is recorded under. name and email come from the login, account from the remote when it knows who it
+// is authenticated as
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Author {
+    pub name: String,
+    pub email: String,
+    pub account: Option<String>,
+}
+
+impl Author {
+    // What to show next to a file, whatever identifies the author best
+    pub fn display_name(&self) -> String {
+        if !self.name.is_empty() {
+            self.name.clone()
+        }else if !self.email.is_empty() {
+            self.email.clone()
+        }else{
+            self.account.clone().unwrap_or_default()
+        }
+    }
+}
+
+// Every revision is stored on its own under this folder as <id>.json, and never changes once written
+pub(crate) const REVISIONS_DIR: &str = ".entangle/revisions";
+
+pub(crate) fn revision_path(id: &str) -> String {
+    format!("{}/{}.json", REVISIONS_DIR, id)
+}
+
+impl Revision {
+    // A new revision on top of parent
+    pub fn new(parent: Option<String>, author: &Author, msg: String, files: Vec<SyncFile>) -> Revision {
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis();
+
+        // The id is the hash of everything else in the revision, so a revision can't be edited after the fact
+        let mut hasher = Sha256::new();
+        hasher.update(serde_json::to_string(&(&parent, &author.name, &author.email, &author.account, timestamp, &msg, &files)).unwrap().as_bytes());
+        let id = format!("{:x}", hasher.finalize());
+
+        Revision {
+            id,
+            parent,
+            author: author.name.clone(),
+            email: author.email.clone(),
+            account: author.account.clone(),
+            timestamp,
+            msg,
+            files,
+        }
+    }
+}
+
+pub(crate) fn parse_revision(bytes: &[u8]) -> Result<Revision, serde_json::Error> {
+    serde_json::from_slice(bytes)
+}
+
+pub(crate) fn read_revision(file_path: &Path) -> Result<Revision, std::io::Error> {
+    Ok(parse_revision(&std::fs::read(file_path)?)?)
+}
+
+pub(crate) fn write_revision(file_path: &Path, revision: &Revision) -> Result<(), std::io::Error> {
+    // Revisions never change, one that is already there was written by an earlier attempt at the same commit
+    if file_path.is_file() {
+        return Ok(());
+    }
+    if let Some(parent) = file_path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    write_atomic(file_path, serde_json::to_string_pretty(revision).unwrap().as_bytes())
+}
+
+// The single <project>.history file older versions kept every revision in. Only read, for the revisions committed
+// before each one got a file of its own
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub(crate) struct History {
+    pub revisions: Vec<Revision>,
+}
+
+impl History {
+    pub fn head(&self) -> Option<&Revision> {
+        self.revisions.last()
+    }
+
+    pub fn get(&self, id: &str) -> Option<&Revision> {
+        self.revisions.iter().find(|rev| rev.id == id)
+    }
+}
+
+pub(crate) fn parse_history(bytes: &[u8]) -> Result<History, serde_json::Error> {
+    // A remote that has never been committed to has no history file yet
+    if bytes.is_empty() {
+        return Ok(History::default());
+    }
+    serde_json::from_slice(bytes)
+}
+
+pub(crate) fn read_history(file_path: &Path) -> Result<History, std::io::Error> {
+    if !file_path.exists() {
+        return Ok(History::default());
+    }
+    let bytes = std::fs::read(file_path)?;
+    Ok(parse_history(&bytes)?)
+}
+
+// What checking out a revision would do to the local project
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub(crate) struct CheckoutPlan {
+    pub overwrite: Vec<SyncFile>,
+    pub create: Vec<SyncFile>,
+    pub delete: Vec<String>,
+}
+
+// Only files that are synced, in both the local baseline and the remote manifest, are deleted. Anything else is work
+// that was never committed, and a checkout has no business throwing it away
+pub(crate) fn plan_checkout(revision: &Revision, local_files: &[SyncFile], baseline: &SyncInfo, manifest: &SyncInfo) -> CheckoutPlan {
+    let mut plan = CheckoutPlan::default();
+
+    for file in &revision.files {
+        match local_files.iter().find(|lf| lf.path == file.path) {
+            Some(lf) if lf.sha256 == file.sha256 => {},
+            Some(_) => plan.overwrite.push(file.clone()),
+            None => plan.create.push(file.clone()),
+        }
+    }
+
+    for file in local_files {
+        let synced = baseline.files.iter().any(|bf| bf.path == file.path) && manifest.files.iter().any(|mf| mf.path == file.path);
+        if synced && !revision.files.iter().any(|rf| rf.path == file.path) {
+            plan.delete.push(file.path.clone());
+        }
+    }
+
+    plan
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testproject::synced_file;
+
+    #[test]
+    fn checkout_only_touches_files_that_differ() {
+        let revision = Revision::new(None, &Author::default(), "Old".to_string(), vec![
+            synced_file("same.sldprt", b"same"),
+            synced_file("changed.sldprt", b"old"),
+            synced_file("gone.sldprt", b"gone"),
+        ]);
+        let local = vec![
+            synced_file("same.sldprt", b"same"),
+            synced_file("changed.sldprt", b"new"),
+            synced_file("added.sldprt", b"added"),
+            synced_file("draft.sldprt", b"draft"),
+        ];
+        // added.sldprt was committed after the old revision, draft.sldprt never was
+        let synced = SyncInfo { files: vec![synced_file("same.sldprt", b"same"), synced_file("added.sldprt", b"added")], ..Default::default() };
+
+        let plan = plan_checkout(&revision, &local, &synced, &synced);
+
+        assert_eq!(plan.overwrite.iter().map(|f| f.path.as_str()).collect::<Vec<_>>(), vec!["changed.sldprt"]);
+        assert_eq!(plan.create.iter().map(|f| f.path.as_str()).collect::<Vec<_>>(), vec!["gone.sldprt"]);
+        assert_eq!(plan.delete, vec!["added.sldprt".to_string()]);
+    }
+}
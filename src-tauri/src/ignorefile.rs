@@ -0,0 +1,112 @@
+use std::path::Path;
+
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+
+use crate::objects::META_DIR;
+
+// Per-project ignore file, same syntax as a .gitignore
+pub(crate) const IGNORE_FILE: &str = ".entangleignore";
+
+// Clutter CAD tools and operating systems leave next to the real files. Checked before the project's .entangleignore,
+// so any of these can be brought back with a negated pattern
+const DEFAULT_IGNORES: &[&str] = &[
+    // Operating systems
+    ".DS_Store",
+    "Thumbs.db",
+    "desktop.ini",
+    // Lock files, SolidWorks and Office write ~$part.sldprt while part.sldprt is open
+    "~$*",
+    "*.lck",
+    "*.lock",
+    // Temporary files and backups
+    "*.tmp",
+    "*.temp",
+    "*.bak",
+    "*~",
+    // SolidWorks backups and auto-recover files
+    "Backup of *",
+    "AutoRecover of *",
+    "*.swbak",
+    "*.swar",
+    // Fusion 360 and Onshape export leftovers
+    "*.f3d.tmp",
+    "*.partial",
+    // Render caches
+    "RenderCache/",
+    "*.rendercache",
+];
+
+// Everything the project's walks, status, commit and pull leave alone
+pub(crate) struct IgnoreRules(Gitignore);
+
+impl IgnoreRules {
+    pub fn load(root: &Path, projectname: &str) -> IgnoreRules {
+        let mut builder = GitignoreBuilder::new(root);
+        // CAD tools on Windows don't keep the case of extensions consistent, *.tmp has to catch FILE.TMP too
+        builder.case_insensitive(true).unwrap();
+
+        // Entangle's own bookkeeping never gets synced
+        for line in [format!("/{}/", META_DIR), format!("/{}.sync", projectname), format!("/{}.history", projectname)] {
+            builder.add_line(None, &line).unwrap();
+        }
+        for line in DEFAULT_IGNORES {
+            builder.add_line(None, line).unwrap();
+        }
+
+        let ignore_file = root.join(IGNORE_FILE);
+        if ignore_file.exists() {
+            // A broken pattern only loses that pattern, the rest of the file still applies
+            if let Some(e) = builder.add(&ignore_file) {
+                eprintln!("Failed to read {}: {}", IGNORE_FILE, e);
+            }
+        }
+
+        match builder.build() {
+            Ok(v) => IgnoreRules(v),
+            Err(e) => {
+                eprintln!("Failed to build ignore rules: {}", e);
+                IgnoreRules(Gitignore::empty())
+            }
+        }
+    }
+
+    // path is relative to the project root, or absolute inside it
+    pub fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+        self.0.matched_path_or_any_parents(path, is_dir).is_ignore()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testproject::{PROJECT, TestDir};
+
+    #[test]
+    fn cad_clutter_and_bookkeeping_are_ignored() {
+        let project = TestDir::new();
+        let rules = IgnoreRules::load(&project.path, PROJECT);
+
+        assert!(rules.is_ignored(Path::new("parts/~$bracket.sldprt"), false));
+        assert!(rules.is_ignored(Path::new("parts/BRACKET.TMP"), false));
+        assert!(rules.is_ignored(Path::new("Backup of bracket.sldprt"), false));
+        assert!(rules.is_ignored(Path::new("RenderCache/frame.png"), false));
+        assert!(rules.is_ignored(Path::new(".entangle/objects/abc"), false));
+        assert!(rules.is_ignored(Path::new("test.sync"), false));
+        assert!(!rules.is_ignored(Path::new("parts/bracket.sldprt"), false));
+        // Only the project's own .sync at the root is bookkeeping
+        assert!(!rules.is_ignored(Path::new("parts/test.sync"), false));
+    }
+
+    #[test]
+    fn the_ignore_file_adds_and_brings_back_patterns() {
+        let project = TestDir::new();
+        project.write(IGNORE_FILE, b"exports/\n*.step\n!keep.bak\n");
+        let rules = IgnoreRules::load(&project.path, PROJECT);
+
+        assert!(rules.is_ignored(Path::new("exports"), true));
+        assert!(rules.is_ignored(Path::new("exports/bracket.stl"), false));
+        assert!(rules.is_ignored(Path::new("parts/bracket.step"), false));
+        assert!(rules.is_ignored(Path::new("old.bak"), false));
+        assert!(!rules.is_ignored(Path::new("keep.bak"), false));
+    }
+}
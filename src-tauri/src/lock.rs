@@ -0,0 +1,96 @@
+use std::{path::Path, fs::{self, Permissions}, time::{UNIX_EPOCH, SystemTime}};
+
+use serde::{Serialize, Deserialize};
+
+// An exclusive reservation on a file. CAD files can't be merged, so whoever holds the lock is the only one who
+// should be changing the file
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct Lock {
+    pub path: String,
+    pub user: String,
+    pub email: String,
+    pub machine: String,
+    pub timestamp: u128,
+}
+
+impl Lock {
+    pub fn new(path: String, user: String, email: String) -> Lock {
+        Lock {
+            path,
+            user,
+            email,
+            machine: gethostname::gethostname().to_string_lossy().into_owned(),
+            timestamp: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis(),
+        }
+    }
+
+    // Locks belong to the account that took them, so they carry over between machines
+    pub fn held_by(&self, email: &str) -> bool {
+        self.email == email
+    }
+}
+
+// Every lock currently held on a remote, stored next to the manifest as <project>.locks
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub(crate) struct Locks {
+    pub locks: Vec<Lock>,
+}
+
+impl Locks {
+    pub fn get(&self, path: &str) -> Option<&Lock> {
+        self.locks.iter().find(|l| l.path == path)
+    }
+
+    pub fn insert(&mut self, lock: Lock) {
+        self.locks.retain(|l| l.path != lock.path);
+        self.locks.push(lock);
+    }
+
+    pub fn remove(&mut self, path: &str) {
+        self.locks.retain(|l| l.path != path);
+    }
+}
+
+pub(crate) fn parse_locks(bytes: &[u8]) -> Result<Locks, serde_json::Error> {
+    // Nobody has locked anything on this remote yet
+    if bytes.is_empty() {
+        return Ok(Locks::default());
+    }
+    serde_json::from_slice(bytes)
+}
+
+pub(crate) fn read_locks(file_path: &Path) -> Result<Locks, std::io::Error> {
+    if !file_path.exists() {
+        return Ok(Locks::default());
+    }
+    let bytes = fs::read(file_path)?;
+    Ok(parse_locks(&bytes)?)
+}
+
+pub(crate) fn write_locks(file_path: &Path, locks: &Locks) -> Result<(), std::io::Error> {
+    crate::write_atomic(file_path, serde_json::to_string_pretty(locks).unwrap().as_bytes())
+}
+
+// Files that aren't locked can be kept read-only locally, so nobody starts editing without taking the lock first
+pub(crate) fn set_readonly(file_path: &Path, readonly: bool) -> Result<(), std::io::Error> {
+    let mut permissions = fs::metadata(file_path)?.permissions();
+    if readonly {
+        permissions.set_readonly(true);
+    }else{
+        make_writable(&mut permissions);
+    }
+    fs::set_permissions(file_path, permissions)
+}
+
+// Only gives write access back to the owner, set_readonly(false) would make the file writable by everyone
+#[cfg(unix)]
+fn make_writable(permissions: &mut Permissions) {
+    use std::os::unix::fs::PermissionsExt;
+    permissions.set_mode(permissions.mode() | 0o200);
+}
+
+#[cfg(not(unix))]
+#[allow(clippy::permissions_set_readonly_false)]
+fn make_writable(permissions: &mut Permissions) {
+    permissions.set_readonly(false);
+}
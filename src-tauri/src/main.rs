@@ -1,23 +1,56 @@
 // Prevents additional console window on Windows in release, DO NOT REMOVE!!
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
+// Tauri commands take their arguments one by one, straight from the frontend
+#![allow(clippy::too_many_arguments)]
 
 pub mod auth;
+pub mod authconfig;
 pub mod gdrive;
 pub mod fabworks;
-
-use std::{sync::{Arc}, path::{Path, PathBuf}, fs::{self, File}, env, process::Command, io::{Write, Read}, collections::HashMap, time::{UNIX_EPOCH, SystemTime}};
+pub mod history;
+pub mod objects;
+pub mod conflict;
+pub mod status;
+pub mod remote;
+pub mod engine;
+pub mod hashcache;
+pub mod ignorefile;
+pub mod lock;
+pub mod credentials;
+pub mod gdhttp;
+pub mod gdindex;
+pub mod gdupload;
+pub mod settings;
+#[cfg(test)]
+mod mockdrive;
+#[cfg(test)]
+mod testproject;
+pub mod error;
+
+use std::{sync::{Arc, atomic::{AtomicUsize, AtomicU64, Ordering}}, path::{Path, PathBuf}, fs::{self, File}, env, io::{Write, Read}, collections::{HashMap, HashSet}, time::{UNIX_EPOCH, SystemTime, Duration, Instant}};
 
 use fabworks::{list_fw_files, push_to_fw};
-use futures::executor;
 use futures_util::lock::Mutex;
-use gdrive::{upload_files_to_google_drive, gd_get_file, gd_delete_file, gd_get_sync, create_folder_gd};
-use auth::GDStruct;
-use git2::{Repository, Signature, StatusOptions, RepositoryOpenFlags, RepositoryInitOptions};
+use gdrive::{gd_get_sync, GoogleDriveRemote};
+use gdupload::UploadProgress;
+use gdhttp::DriveSession;
+use history::{Author, Revision, CheckoutPlan};
+use engine::{CommitOutcome, CommitPlan, Status};
+use conflict::{Conflict, Keep};
+use status::FileStatus;
+use remote::{Remote, LocalFolderRemote};
+use hashcache::{HashCache, Fingerprint};
+use ignorefile::IgnoreRules;
+use lock::Lock;
+use authconfig::AuthProvider;
+use settings::Settings;
+use error::EntangleError;
+use git2::Repository;
 use serde::{Serialize, Deserialize};
 use serde_with::serde_as;
-use tokio::{task::spawn_blocking, sync::Semaphore};
+use tokio::sync::oneshot;
 use walkdir::WalkDir;
-use hex_literal::hex;
+use rayon::prelude::*;
 use sha2::{Sha256, Digest};
 struct MutexState(Mutex<State>);
 
@@ -25,31 +58,111 @@ struct State{
     signature_email: Option<String>,
     signature_name: Option<String>,
     repo_path: Option<String>,
-    gdstruct: Option<GDStruct>,
+    drive: Option<DriveSession>,
+    login_cancel: Option<oneshot::Sender<()>>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 struct SyncFile {
     name: String,
     path: String,
     sha256: String,
+    #[serde(default)]
+    modified_by: Option<String>,
 }
 
+// Drive file ids for the paths in a project folder, see gdindex
 #[serde_as]
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Default, Serialize, Deserialize)]
 struct GDriveIDs {
+    // Path -> file id
     #[serde_as(as = "Vec<(_, _)>")]
     ids: HashMap<String, String>,
+    // File id -> id of the folder it's in
     #[serde_as(as = "Vec<(_, _)>")]
-    parents: HashMap<String, String>
+    parents: HashMap<String, String>,
+    // Paths that are folders
+    #[serde(default)]
+    folders: HashSet<String>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Default, Serialize, Deserialize)]
 struct SyncInfo {
     files: Vec<SyncFile>,
     folders: Vec<String>,
     msg: String,
     author: String,
+    #[serde(default)]
+    revision: Option<String>,
+    #[serde(default)]
+    conflicts: Vec<Conflict>,
+}
+
+// How far along hashing the project is, sent to the frontend as hash-progress events
+#[derive(Debug, Clone, Serialize)]
+struct HashProgress {
+    files_done: usize,
+    files_total: usize,
+    bytes_done: u64,
+    bytes_total: u64,
+}
+
+type OnHashProgress = dyn Fn(HashProgress) + Sync;
+
+// CAD files run into the hundreds of megabytes, so they are read a megabyte at a time
+const HASH_BUFFER_SIZE: usize = 1024 * 1024;
+
+// Progress is only reported this often, a project full of small files would otherwise flood the frontend
+const HASH_PROGRESS_INTERVAL: Duration = Duration::from_millis(100);
+
+// Counts what the hashing workers have gotten through
+struct HashTracker<'a> {
+    files_done: AtomicUsize,
+    files_total: usize,
+    bytes_done: AtomicU64,
+    bytes_total: u64,
+    last_report: std::sync::Mutex<Instant>,
+    on_progress: &'a OnHashProgress,
+}
+
+impl<'a> HashTracker<'a> {
+    fn new(files_total: usize, bytes_total: u64, on_progress: &'a OnHashProgress) -> HashTracker<'a> {
+        HashTracker {
+            files_done: AtomicUsize::new(0),
+            files_total,
+            bytes_done: AtomicU64::new(0),
+            bytes_total,
+            last_report: std::sync::Mutex::new(Instant::now()),
+            on_progress,
+        }
+    }
+
+    fn add_bytes(&self, bytes: u64) {
+        self.bytes_done.fetch_add(bytes, Ordering::Relaxed);
+        self.report(false);
+    }
+
+    fn file_done(&self) {
+        self.files_done.fetch_add(1, Ordering::Relaxed);
+        self.report(false);
+    }
+
+    fn report(&self, force: bool) {
+        {
+            let mut last_report = self.last_report.lock().unwrap();
+            if !force && last_report.elapsed() < HASH_PROGRESS_INTERVAL {
+                return;
+            }
+            *last_report = Instant::now();
+        }
+
+        (self.on_progress)(HashProgress {
+            files_done: self.files_done.load(Ordering::Relaxed),
+            files_total: self.files_total,
+            bytes_done: self.bytes_done.load(Ordering::Relaxed),
+            bytes_total: self.bytes_total,
+        });
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -72,11 +185,12 @@ async fn main() {
         signature_email: None,
         signature_name: None,
         repo_path: None,
-        gdstruct: None,
+        drive: credentials::load_session().map(DriveSession::new),
+        login_cancel: None,
     }));
     tauri::Builder::default()
         .manage(Arc::new(state))
-        .invoke_handler(tauri::generate_handler![count_dir, open_repo, list_files, login, commit, validate_gsfile, push, initialize, gd_auth, gd_initialize, list_files_gd, gd_commit, gd_pull, get_fw_files, send_to_fw, gd_get_sync_file, save_proj, get_projs])
+        .invoke_handler(tauri::generate_handler![count_dir, open_repo, list_files, login, commit, initialize, gd_auth, gd_initialize, list_files_gd, gd_commit, plan_commit, plan_commit_gd, gd_pull, pull, get_fw_files, send_to_fw, gd_get_sync_file, save_proj, get_projs, list_history, list_history_gd, restore_file, restore_file_gd, checkout_revision, checkout_revision_gd, resolve_conflict, clear_hash_cache, clear_drive_index, lock_file, lock_file_gd, unlock_file, unlock_file_gd, gd_logged_in, gd_logout, cancel_login, get_auth_provider, set_auth_provider, get_settings, set_settings])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
 }
@@ -86,138 +200,267 @@ struct FileData {
     name: String,
     select: bool,
     path: String,
-    status: u8
+    status: FileStatus,
+    #[serde(default)]
+    lock: Option<Lock>,
+    // Who committed the version of the file on the remote
+    #[serde(default)]
+    modified_by: Option<String>,
 }
 
 #[tauri::command]
-async fn count_dir(path: String) -> usize {
-    WalkDir::new(path).into_iter().count()
+async fn count_dir(path: String) -> Result<usize, EntangleError> {
+    let entries = WalkDir::new(path).into_iter()
+        .collect::<Result<Vec<_>, walkdir::Error>>()
+        .map_err(|e| log_error("Failed to count the files in the folder", std::io::Error::from(e)))?;
+    Ok(entries.len())
 }
 
 #[tauri::command]
-async fn login(state: tauri::State<'_, Arc<MutexState>>, email: String, name: String) -> Result<bool, bool> {
+async fn login(state: tauri::State<'_, Arc<MutexState>>, email: String, name: String) -> Result<bool, EntangleError> {
     let mut lclstate = state.inner().0.lock().await;
     lclstate.signature_email = Some(email);
     lclstate.signature_name = Some(name);
 
-    return Ok(true);
+    Ok(true)
 }
 
 #[tauri::command]
-fn initialize(state: tauri::State<'_, Arc<MutexState>>, path: String, projectname: String) -> bool {
+async fn initialize(state: tauri::State<'_, Arc<MutexState>>, path: String, projectname: String) -> Result<bool, EntangleError> {
     let folder_path = Path::new(&path);
+    let author = signature(&state).await;
 
     // Create a SyncInfo struct
     let sync_info = SyncInfo {
         files: Vec::new(),
         msg: String::new(), // initialize with a blank message
-        author: String::new(), // initialize with a blank author,
-        folders: Vec::new()
+        author: author.display_name(), // whoever is logged in, the same as a commit records
+        folders: Vec::new(),
+        revision: None,
+        conflicts: Vec::new(),
     };
 
     // Serialize SyncInfo to JSON
-    let sync_info_json = serde_json::to_string_pretty(&sync_info).unwrap();
+    let sync_info_json = serde_json::to_string_pretty(&sync_info)?;
 
     let sync_file_path = folder_path.join(format!("{}.sync", projectname));
-    let mut file = File::create(sync_file_path).expect("Failed to create sync file");
-    file.write_all(sync_info_json.as_bytes()).expect("Failed to write to sync file");
+    write_sync_file(&sync_file_path, &sync_info_json).map_err(|e| log_error("Failed to create sync file", e))?;
 
-    true
+    Ok(true)
 }
 
 #[tauri::command]
-async fn gd_initialize(state: tauri::State<'_, Arc<MutexState>>, path: String, id: String, projectname: String) -> Result<bool, bool> {
-    let mut lclstate: futures_util::lock::MutexGuard<'_, State> = state.inner().0.lock().await;
+async fn gd_initialize(window: tauri::Window, state: tauri::State<'_, Arc<MutexState>>, path: String, id: String, projectname: String) -> Result<bool, EntangleError> {
+    let remote = gd_remote(&state, &id, &projectname).await?.with_upload_progress(emit_upload_progress(&window));
+    let author = signature(&state).await;
+
+    let result = async {
+        // A new project starts out with an empty baseline and an empty remote, so everything gets committed as new
+        let sync_info = SyncInfo::default();
+        let sync_file_path = Path::new(&path).join(format!("{}.sync", projectname));
+        write_sync_file(&sync_file_path, &serde_json::to_string_pretty(&sync_info)?)?;
+        remote.write_manifest(&sync_info).await?;
+
+        let status = engine::status(&remote, Path::new(&path), &projectname, &emit_hash_progress(&window)).await?;
+        engine::commit(&remote, Path::new(&path), &projectname, &status.files, status.revision, "Initialize project".to_string(), author, false, settings::load_settings().upload_concurrency, &emit_hash_progress(&window)).await
+    }.await;
+
+    result.map_err(|e| log_error("Failed to initialize project", e))?;
+    Ok(true)
+}
 
-    let folder_path = Path::new(&path);
-    let client = match &lclstate.gdstruct {
-        Some(v) => {&v.drive},
-        None => {return Ok(false)},
-    };
+// How long the browser login waits for Google before giving up, unless the frontend asks for something else
+const LOGIN_TIMEOUT_SECS: u64 = 300;
+
+#[tauri::command]
+async fn gd_auth(state: tauri::State<'_, Arc<MutexState>>, timeout: Option<u64>) -> Result<bool, EntangleError> {
+    let (cancel_tx, cancel_rx) = oneshot::channel();
+    {
+        let mut lclstate = state.inner().0.lock().await;
+        // A login that was still waiting gets cancelled, only the newest one can finish
+        if let Some(previous) = lclstate.login_cancel.replace(cancel_tx) {
+            let _ = previous.send(());
+        }
+    }
 
-    let tokens = &lclstate.gdstruct.as_ref().unwrap().token;
+    // State isn't held while the browser is open, so cancel_login can get to it
+    let result = auth::auth(authconfig::load_provider(), Duration::from_secs(timeout.unwrap_or(LOGIN_TIMEOUT_SECS)), cancel_rx).await;
 
-    update_hashes(state, path.clone(), projectname.clone());
+    let mut lclstate = state.inner().0.lock().await;
+    lclstate.login_cancel = None;
 
-    let sync_file_path = folder_path.join(format!("{}.sync", projectname));
+    let gds = result.map_err(|e| log_error("Failed to log in to Google Drive", e))?;
+    credentials::save_session(&gds);
+    lclstate.drive = Some(DriveSession::new(gds));
+    Ok(true)
+}
 
-    upload_files_to_google_drive(vec![Box::from(sync_file_path.clone())], &path.clone(), &id.clone(), client, tokens, Some(format!("{}.sync", projectname))).await;
-    let mut sync = read_sync_file(sync_file_path.clone()).unwrap();
-    let files: Vec<Box<Path>> = (&sync.files).into_iter().map(|entry| {
-        Box::from(folder_path.join(entry.path.clone()))
-    }).collect();
+#[tauri::command]
+async fn get_auth_provider() -> Result<AuthProvider, EntangleError> {
+    Ok(authconfig::load_provider())
+}
 
-    upload_files_to_google_drive(files, &path, &id, client, tokens, None).await;
-    
-    write_sync_file(&sync_file_path, &serde_json::to_string(&sync).unwrap()).unwrap();
+// Tokens only work with the client that handed them out, so switching providers logs out of Drive
+#[tauri::command]
+async fn set_auth_provider(state: tauri::State<'_, Arc<MutexState>>, provider: AuthProvider) -> Result<bool, EntangleError> {
+    if provider == authconfig::load_provider() {
+        return Ok(true);
+    }
+
+    authconfig::save_provider(&provider).map_err(|e| log_error("Failed to save login settings", e))?;
 
+    let mut lclstate = state.inner().0.lock().await;
+    lclstate.drive = None;
+    credentials::clear_session();
     Ok(true)
 }
 
 #[tauri::command]
-fn gd_auth(state: tauri::State<'_, Arc<MutexState>>) -> Result<bool, bool> {
-    let gds = executor::block_on(auth::auth());
+async fn get_settings() -> Result<Settings, EntangleError> {
+    Ok(settings::load_settings())
+}
 
-    let mut lclstate = executor::block_on(state.inner().0.lock());
+#[tauri::command]
+async fn set_settings(settings: Settings) -> Result<bool, EntangleError> {
+    settings::save_settings(&settings).map_err(|e| log_error("Failed to save settings", e))?;
+    Ok(true)
+}
 
-    lclstate.gdstruct = Some(gds);
+// Stop a login that is still waiting on the browser
+#[tauri::command]
+async fn cancel_login(state: tauri::State<'_, Arc<MutexState>>) -> Result<bool, EntangleError> {
+    let mut lclstate = state.inner().0.lock().await;
+    match lclstate.login_cancel.take() {
+        Some(cancel) => Ok(cancel.send(()).is_ok()),
+        None => Ok(false),
+    }
+}
 
-    Ok(true)
+// Whether a Drive login was restored from the last launch
+#[tauri::command]
+async fn gd_logged_in(state: tauri::State<'_, Arc<MutexState>>) -> Result<bool, EntangleError> {
+    let lclstate = state.inner().0.lock().await;
+    // A saved broker login that has run out needs the browser again
+    match &lclstate.drive {
+        Some(session) => Ok(session.is_usable().await),
+        None => Ok(false),
+    }
 }
 
-fn update_hashes(state: tauri::State<'_, Arc<MutexState>>, path: String, projectname: String) -> bool {
-    let folder_path = Path::new(&path);
+#[tauri::command]
+async fn gd_logout(state: tauri::State<'_, Arc<MutexState>>) -> Result<bool, EntangleError> {
+    let mut lclstate = state.inner().0.lock().await;
+    lclstate.drive = None;
+    credentials::clear_session();
+    Ok(true)
+}
 
-    // Open the folder path, listing all of the files recursively
-    let files: Vec<SyncFile> = WalkDir::new(folder_path)
+// Everything in the project that isn't ignored, ignored folders are not descended into
+fn walk_project<'a>(folder_path: &'a Path, rules: &'a IgnoreRules) -> impl Iterator<Item = walkdir::DirEntry> + 'a {
+    WalkDir::new(folder_path)
         .into_iter()
-        .filter_map(|entry| entry.ok())
-        .filter(|entry| !entry.file_type().is_dir())
-        .filter(|entry| entry.file_name() != ".DS_Store") // Ignore .DS_Store files
-        .filter(|entry| entry.file_name().to_str().unwrap() != format!("{projectname}.sync"))
-        .map(|entry| {
+        .filter_entry(move |entry| {
             let relative_path = entry.path().strip_prefix(folder_path).unwrap();
-            let md5 = compute_sha256(entry.path()).unwrap();
-
-            SyncFile {
-                name: entry.file_name().to_str().unwrap().to_owned(),
-                path: relative_path.to_string_lossy().into_owned(),
-                sha256: md5,
-            }
+            !rules.is_ignored(relative_path, entry.file_type().is_dir())
         })
-        .collect();
-
-    let folders: Vec<String> = WalkDir::new(folder_path)
-        .into_iter()
         .filter_map(|entry| entry.ok())
-        .filter(|entry| entry.file_type().is_dir())
+}
+
+// Hash every file in the project that isn't ignored. Files whose size, mtime and inode haven't changed since the
+// last run reuse the hash from the project's hash cache, the rest are hashed in parallel
+fn hash_local_files(folder_path: &Path, rules: &IgnoreRules, on_progress: &OnHashProgress) -> Result<Vec<SyncFile>, std::io::Error> {
+    let previous = HashCache::load(folder_path);
+
+    let entries: Vec<(walkdir::DirEntry, Fingerprint)> = walk_project(folder_path, rules)
+        .filter(|entry| !entry.file_type().is_dir())
         .map(|entry| {
-            entry.path().strip_prefix(folder_path).unwrap().to_str().unwrap().to_string()
+            let fingerprint = Fingerprint::of(&entry.metadata()?);
+            Ok((entry, fingerprint))
         })
-        .collect();
+        .collect::<Result<_, walkdir::Error>>()?;
 
-    // Create a SyncInfo struct
-    let sync_info = SyncInfo {
-        files,
-        msg: String::new(), // initialize with a blank message
-        author: String::new(), // initialize with a blank author
-        folders
-    };
+    let tracker = HashTracker::new(entries.len(), entries.iter().map(|(_, fingerprint)| fingerprint.size()).sum(), on_progress);
 
-    // Serialize SyncInfo to JSON
-    let sync_info_json = serde_json::to_string_pretty(&sync_info).unwrap();
+    // Hashing blocks, so the async runtime is told to move its other work off this thread while it runs
+    let hashed: Vec<(SyncFile, Fingerprint)> = tokio::task::block_in_place(|| -> Result<_, std::io::Error> {
+        entries
+            .into_par_iter()
+            .map(|(entry, fingerprint)| {
+                let relative_path = entry.path().strip_prefix(folder_path).unwrap().to_string_lossy().into_owned();
 
-    let sync_file_path = folder_path.join(format!("{}.sync", projectname));
-    let mut file = File::create(sync_file_path).expect("Failed to create sync file");
-    file.write_all(sync_info_json.as_bytes()).expect("Failed to write to sync file");
+                let sha256 = match previous.lookup(&relative_path, &fingerprint) {
+                    Some(v) => {
+                        tracker.add_bytes(fingerprint.size());
+                        v
+                    }
+                    // CAD tools can hold files open exclusively while they're being saved
+                    None => hash_file(entry.path(), |n| tracker.add_bytes(n))
+                        .map_err(|e| EntangleError::Io(format!("Failed to read {}: {}", relative_path, e)))?,
+                };
+                tracker.file_done();
+
+                let file = SyncFile {
+                    name: entry.file_name().to_string_lossy().into_owned(),
+                    path: relative_path,
+                    sha256,
+                    modified_by: None,
+                };
+                Ok((file, fingerprint))
+            })
+            .collect()
+    })?;
+    tracker.report(true);
+
+    // Only files that still exist make it into the new cache
+    let mut cache = HashCache::default();
+    for (file, fingerprint) in &hashed {
+        cache.insert(file.path.clone(), fingerprint.clone(), file.sha256.clone());
+    }
+    if let Err(e) = cache.save(folder_path) {
+        eprintln!("Failed to save hash cache: {}", e);
+    }
+
+    Ok(hashed.into_iter().map(|(file, _)| file).collect())
+}
+
+// Forwards hashing progress to the frontend as hash-progress events
+fn emit_hash_progress(window: &tauri::Window) -> impl Fn(HashProgress) + Sync {
+    let window = window.clone();
+    move |progress| {
+        if let Err(e) = window.emit("hash-progress", progress) {
+            eprintln!("Failed to send hash progress: {}", e);
+        }
+    }
+}
+
+// Forwards file upload progress to the frontend as upload-progress events
+fn emit_upload_progress(window: &tauri::Window) -> impl Fn(UploadProgress) + Send + Sync + 'static {
+    let window = window.clone();
+    move |progress| {
+        if let Err(e) = window.emit("upload-progress", progress) {
+            eprintln!("Failed to send upload progress: {}", e);
+        }
+    }
+}
 
-    true
+fn list_local_folders(folder_path: &Path, rules: &IgnoreRules) -> Vec<String> {
+    walk_project(folder_path, rules)
+        .filter(|entry| entry.file_type().is_dir())
+        .map(|entry| {
+            entry.path().strip_prefix(folder_path).unwrap().to_string_lossy().into_owned()
+        })
+        .collect()
 }
 
 fn compute_sha256(file_path: &Path) -> Result<String, std::io::Error> {
+    hash_file(file_path, |_| {})
+}
+
+// Streams the file through the hasher, calling on_read with the size of every chunk read
+fn hash_file(file_path: &Path, on_read: impl Fn(u64)) -> Result<String, std::io::Error> {
     let mut file = File::open(file_path)?;
     let mut hasher = Sha256::new();
-    let mut buffer = [0; 1024];
+    let mut buffer = vec![0; HASH_BUFFER_SIZE];
 
     loop {
         let bytes_read = file.read(&mut buffer)?;
@@ -225,6 +468,7 @@ fn compute_sha256(file_path: &Path) -> Result<String, std::io::Error> {
             break;
         }
         hasher.update(&buffer[..bytes_read]);
+        on_read(bytes_read as u64);
     }
 
     let result = hasher.finalize();
@@ -232,376 +476,68 @@ fn compute_sha256(file_path: &Path) -> Result<String, std::io::Error> {
 }
 
 #[tauri::command]
-async fn open_repo(state: tauri::State<'_, Arc<MutexState>>, path: String) -> Result<bool, bool> {
-    let repo = match Repository::open(path.clone()) {
-        Ok(v) => {v},
-        Err(e) => {println!("{}", e); return Ok(true);}
-    };
+async fn open_repo(state: tauri::State<'_, Arc<MutexState>>, path: String) -> Result<bool, EntangleError> {
+    Repository::open(&path).map_err(|e| log_error("Failed to open repository", EntangleError::NotFound(e.message().to_string())))?;
 
     let mut lclstate = state.inner().0.lock().await;
     lclstate.repo_path = Some(path.clone());
-    return Ok(true);
+    Ok(true)
 }
 
-#[tauri::command]
-fn list_files(state: tauri::State<'_, Arc<MutexState>>, path: String, projectname: String, remotepath: String, remoteproject: String) -> Vec<FileData> {
-    let folder_path = Path::new(&path);
-
-    // Open the folder path, listing all of the files recursively
-    let local_files: HashMap<String, SyncFile> = WalkDir::new(folder_path)
-        .into_iter()
-        .filter_map(|entry| entry.ok())
-        .filter(|entry| !entry.file_type().is_dir())
-        .filter(|entry| entry.file_name() != ".DS_Store") // Ignore .DS_Store files
-        .filter(|entry| entry.file_name().to_str().unwrap() != format!("{projectname}.sync"))
-        .filter(|entry| entry.file_name().to_str().unwrap() != format!("{remoteproject}.sync"))
-        .map(|entry| {
-            let relative_path = entry.path().strip_prefix(folder_path).unwrap();
-            let md5 = compute_sha256(entry.path()).unwrap();
-
-            (relative_path.to_string_lossy().into_owned(), SyncFile { name: entry.file_name().to_str().unwrap().to_owned(), path: relative_path.to_string_lossy().into_owned(), sha256: md5 })
-        })
-        .collect();
-
-    let files: Vec<SyncFile> = WalkDir::new(folder_path)
-        .into_iter()
-        .filter_map(|entry| entry.ok())
-        .filter(|entry| !entry.file_type().is_dir())
-        .filter(|entry| entry.file_name() != ".DS_Store") // Ignore .DS_Store files
-        .filter(|entry| entry.file_name().to_str().unwrap() != format!("{projectname}.sync"))
-        .filter(|entry| entry.file_name().to_str().unwrap() != format!("{remoteproject}.sync"))
-        .map(|entry| {
-            let relative_path = entry.path().strip_prefix(folder_path).unwrap();
-            let md5 = compute_sha256(entry.path()).unwrap();
-
-            SyncFile {
-                name: entry.file_name().to_str().unwrap().to_owned(),
-                path: relative_path.to_string_lossy().into_owned(),
-                sha256: md5,
-            }
-        })
-        .collect();
-
-    let folders: Vec<String> = WalkDir::new(folder_path)
-        .into_iter()
-        .filter_map(|entry| entry.ok())
-        .filter(|entry| entry.file_type().is_dir())
-        .map(|entry| {
-            entry.path().strip_prefix(folder_path).unwrap().to_str().unwrap().to_string()
-        })
-        .collect();
+// The Drive login, as long as the user has logged in to Google Drive
+async fn gd_session(state: &tauri::State<'_, Arc<MutexState>>) -> Result<DriveSession, EntangleError> {
+    let lclstate = state.inner().0.lock().await;
+    lclstate.drive.clone().ok_or(EntangleError::NotLoggedIn)
+}
 
-    // Create a SyncInfo struct
-    let sync_info = SyncInfo {
-        files,
-        msg: String::new(), // initialize with a blank message
-        author: String::new(), // initialize with a blank author
-        folders
-    };
+// The Drive remote for a project, as long as the user has logged in to Google Drive
+async fn gd_remote(state: &tauri::State<'_, Arc<MutexState>>, remoteid: &str, projectname: &str) -> Result<GoogleDriveRemote, EntangleError> {
+    gd_session(state).await.map(|session| GoogleDriveRemote::new(&session, remoteid, projectname))
+}
 
-    // Deserialize the content of the local .sync file
-    let local_sync_file_path = folder_path.join(format!("{}.sync", projectname));
-    let local_sync_info: SyncInfo = read_sync_file(local_sync_file_path).unwrap();
-
-    // Deserialize the content of the remote .sync file
-    let remote_sync_file_path = Path::new(&remotepath).join(format!("{}.sync", remoteproject));
-    let remote_sync_info: SyncInfo = read_sync_file(remote_sync_file_path).unwrap();
-
-    // Match the file names between the local, remote, and project files
-    //Status is defined as the following
-    //If the file exists in all three places and the sha hashes are the same, be zero
-    //If a file exists in all three places, but the remote sha is different and the project and files sha are the same, be 1
-    //If a file exists in all three places, but the remote and project sha are the same but the files sha is different, be 2
-    //If a file exists in all three places, but the remote and files sha are the same but the project sha is different, be 3
-    //If a file exists in all three places, but the shas are all different, be 4
-    //If a file exists in remote, but not in project and files, be 5
-    //If a file exists in remote and project, but not in files, be 6
-    //If a file exists in project and files but not in remote, be 7
-    //If a file exists in files but not project or remote, be 8
-    let mut result: Vec<FileData> = sync_info
-        .files
-        .into_iter()
-        .map(|local_file| {
-            let remote_file = remote_sync_info.files.iter().find(|rf: &&SyncFile| rf.path == local_file.path.clone());
-            let project_file = local_sync_info.files.iter().find(|rf: &&SyncFile| rf.path == local_file.path.clone());
-
-            let selected = true; // Assuming all files are selected by default
-            
-            let status = match (remote_file, project_file) {
-                (Some(rf), Some(pf)) if ((rf.sha256 == pf.sha256) && (local_file.sha256 == rf.sha256)) => 0,
-                (Some(rf), Some(pf)) => {
-                    if local_file.sha256 == pf.sha256 {
-                        1
-                    } else if pf.sha256 == rf.sha256 {
-                        2
-                    } else if local_file.sha256 == rf.sha256 {
-                        3
-                    } else {
-                        4
-                    }
-                }
-                (Some(_), None) => 5,
-                (Some(_), Some(_)) => 6,
-                (None, Some(_)) => 7,
-                (None, None) => 8,
-            };
-
-            FileData {
-                name: local_file.name,
-                path: local_file.path,
-                select: selected,
-                status,
-            }
-        })
-        .collect();
-
-    // Include files from remote that are not in local
-    result.extend(
-        remote_sync_info
-            .files
-            .into_iter()
-            .filter(|rf| !local_files.contains_key(&rf.path))
-            .map(|rf| {
-                let project_file = local_sync_info.files.iter().find(|rf2: &&SyncFile| rf2.path == rf.path.clone());
-
-                if project_file.is_some(){
-                    FileData {
-                        name: rf.path.clone(),
-                        path: rf.path.clone(),
-                        select: true, // Assuming all files from remote are selected by default
-                        status: 6,
-                    }
-                }else{
-                    FileData {
-                        name: rf.path.clone(),
-                        path: rf.path.clone(),
-                        select: true, // Assuming all files from remote are selected by default
-                        status: 5,
-                    }
-                }
-            }),
-    );
-
-    // Handle status 8: Folder exists in sync_info.folders but not in local_sync_info.folders
-    result.extend(
-        sync_info.folders.iter()
-            .filter(|sf| Path::new(sf).is_dir() && !local_sync_info.folders.contains(sf))
-            .map(|sf| {
-                FileData { name: sf.clone(), select: false, path: sf.clone(), status: 8 }
-            })
-    );
-
-    // Handle status 6: Folder exists in local_sync_info.folders but not in sync_info.folders
-    result.extend(
-        local_sync_info.folders.iter()
-            .filter(|lf| Path::new(lf).is_dir() && !sync_info.folders.contains(lf))
-            .map(|lf| {
-                FileData { name: lf.clone(), select: false, path: lf.clone(), status: 6 }
-            })
-    );
-
-    // Handle status 5: Folder exists in remote_sync_info.folders but not in sync_info.folders
-    result.extend(
-        remote_sync_info.folders.iter()
-            .filter(|rf| Path::new(rf).is_dir() && !sync_info.folders.contains(rf))
-            .map(|rf| {
-                FileData { name: rf.clone(), select: false, path: rf.clone(), status: 5 }
-            })
-    );
-
-    // Handle status 7: Folder exists in sync_info.folders but not in remote_sync_info.folders
-    result.extend(
-        sync_info.folders.iter()
-            .filter(|rf| Path::new(rf).is_dir() && !remote_sync_info.folders.contains(rf))
-            .map(|rf| {
-                FileData { name: rf.clone(), select: false, path: rf.clone(), status: 7 }
-            })
-    );
+// Commands still log what went wrong, the frontend gets the same error to show
+fn log_error(context: &str, e: impl Into<EntangleError>) -> EntangleError {
+    let e = e.into();
+    eprintln!("{}: {}", context, e);
+    e
+}
 
-    result
+// The name and email from the login, commits and locks are recorded under them
+async fn signature(state: &tauri::State<'_, Arc<MutexState>>) -> Author {
+    let lclstate = state.inner().0.lock().await;
+    Author {
+        name: lclstate.signature_name.clone().unwrap_or_default(),
+        email: lclstate.signature_email.clone().unwrap_or_default(),
+        account: None,
+    }
 }
 
 #[tauri::command]
-async fn list_files_gd(state: tauri::State<'_, Arc<MutexState>>, path: String, projectname: String, remote_drive: String) -> Result<Vec<FileData>, ()> {
-    let folder_path = Path::new(&path);
-    let mut lclstate: futures_util::lock::MutexGuard<'_, State> = state.inner().0.lock().await;
+async fn list_files(window: tauri::Window, path: String, projectname: String, remotepath: String, remoteproject: String) -> Result<Status, EntangleError> {
+    let remote = LocalFolderRemote::new(&remotepath, &remoteproject);
 
-    // Open the folder path, listing all of the files recursively
-    let local_files: HashMap<String, SyncFile> = WalkDir::new(folder_path)
-        .into_iter()
-        .filter_map(|entry| entry.ok())
-        .filter(|entry| !entry.file_type().is_dir())
-        .filter(|entry| entry.file_name() != ".DS_Store") // Ignore .DS_Store files
-        .filter(|entry| entry.file_name().to_str().unwrap() != format!("{projectname}.sync"))
-        .filter(|entry| entry.file_name().to_str().unwrap() != format!("{projectname}.rmsync"))
-        .map(|entry| {
-            let relative_path = entry.path().strip_prefix(folder_path).unwrap();
-            let md5 = compute_sha256(entry.path()).unwrap();
-
-            (relative_path.to_string_lossy().into_owned(), SyncFile { name: entry.file_name().to_str().unwrap().to_owned(), path: relative_path.to_string_lossy().into_owned(), sha256: md5 })
-        })
-        .collect();
-
-    let files: Vec<SyncFile> = WalkDir::new(folder_path)
-        .into_iter()
-        .filter_map(|entry| entry.ok())
-        .filter(|entry| !entry.file_type().is_dir())
-        .filter(|entry| entry.file_name() != ".DS_Store") // Ignore .DS_Store files
-        .filter(|entry| entry.file_name().to_str().unwrap() != format!("{projectname}.sync"))
-        .filter(|entry| entry.file_name().to_str().unwrap() != format!("{projectname}.rmsync"))
-        .map(|entry| {
-            let relative_path = entry.path().strip_prefix(folder_path).unwrap();
-            let md5 = compute_sha256(entry.path()).unwrap();
-
-            SyncFile {
-                name: entry.file_name().to_str().unwrap().to_owned(),
-                path: relative_path.to_string_lossy().into_owned(),
-                sha256: md5,
-            }
-        })
-        .collect();
+    engine::status(&remote, Path::new(&path), &projectname, &emit_hash_progress(&window)).await.map_err(|e| log_error("Failed to list files", e))
+}
 
-    let folders: Vec<String> = WalkDir::new(folder_path)
-        .into_iter()
-        .filter_map(|entry| entry.ok())
-        .filter(|entry| entry.file_type().is_dir())
-        .map(|entry| {
-            entry.path().strip_prefix(folder_path).unwrap().to_str().unwrap().to_string()
-        })
-        .collect();
+// Throw away the Drive ids cached for a project folder, they get looked up again on the next listing
+#[tauri::command]
+fn clear_drive_index(remoteid: String) -> Result<bool, EntangleError> {
+    gdindex::clear_index(&remoteid).map_err(|e| log_error("Failed to clear Drive id cache", e))?;
+    Ok(true)
+}
 
-    // Create a SyncInfo struct
-    let sync_info = SyncInfo {
-        files,
-        msg: String::new(), // initialize with a blank message
-        author: String::new(), // initialize with a blank author
-        folders
-    };
+// Throw away the project's cached hashes, so the next listing rehashes every file
+#[tauri::command]
+fn clear_hash_cache(path: String) -> Result<bool, EntangleError> {
+    hashcache::clear_cache(Path::new(&path)).map_err(|e| log_error("Failed to clear hash cache", e))?;
+    Ok(true)
+}
 
-    // Deserialize the content of the local .sync file
-    let local_sync_file_path = folder_path.join(format!("{}.sync", projectname));
-    let local_sync_info: SyncInfo = read_sync_file(local_sync_file_path).unwrap();
-
-    println!("{}.sync", projectname);
-    // Deserialize the content of the remote .sync file
-    let bytes = gd_get_file(&format!("{}.sync", projectname), &remote_drive, &lclstate.gdstruct.as_ref().unwrap().drive, &lclstate.gdstruct.as_ref().unwrap().token).await.unwrap();
-    let rmtext = String::from_utf8(bytes).unwrap();
-    let remote_sync_info: SyncInfo = serde_json::from_str(&rmtext).unwrap();
-
-    // Match the file names between the local, remote, and project files
-    //Status is defined as the following
-    //If the file exists in all three places and the sha hashes are the same, be zero
-    //If a file exists in all three places, but the remote sha is different and the project and files sha are the same, be 1
-    //If a file exists in all three places, but the remote and project sha are the same but the files sha is different, be 2
-    //If a file exists in all three places, but the remote and files sha are the same but the project sha is different, be 3
-    //If a file exists in all three places, but the shas are all different, be 4
-    //If a file exists in remote, but not in project and files, be 5
-    //If a file exists in remote and project, but not in files, be 6
-    //If a file exists in project and files but not in remote, be 7
-    //If a file exists in files but not project or remote, be 8
-    let mut result: Vec<FileData> = sync_info
-        .files
-        .into_iter()
-        .map(|local_file| {
-            let remote_file = remote_sync_info.files.iter().find(|rf: &&SyncFile| rf.path == local_file.path.clone());
-            let project_file = local_sync_info.files.iter().find(|rf: &&SyncFile| rf.path == local_file.path.clone());
-
-            let selected = true; // Assuming all files are selected by default
-            
-            let status = match (remote_file, project_file) {
-                (Some(rf), Some(pf)) if ((rf.sha256 == pf.sha256) && (local_file.sha256 == rf.sha256)) => 0,
-                (Some(rf), Some(pf)) => {
-                    if local_file.sha256 == pf.sha256 {
-                        1
-                    } else if pf.sha256 == rf.sha256 {
-                        2
-                    } else if local_file.sha256 == rf.sha256 {
-                        3
-                    } else {
-                        4
-                    }
-                }
-                (Some(_), None) => 5,
-                (Some(_), Some(_)) => 6,
-                (None, Some(_)) => 7,
-                (None, None) => 8,
-            };
-
-            FileData {
-                name: local_file.name,
-                path: local_file.path,
-                select: selected,
-                status,
-            }
-        })
-        .collect();
-
-    // Include files from remote that are not in local
-    result.extend(
-        remote_sync_info
-            .files
-            .into_iter()
-            .filter(|rf| !local_files.contains_key(&rf.path))
-            .map(|rf| {
-                let project_file = local_sync_info.files.iter().find(|rf2: &&SyncFile| rf2.path == rf.path.clone());
-
-                if project_file.is_some(){
-                    FileData {
-                        name: rf.path.clone(),
-                        path: rf.path.clone(),
-                        select: true, // Assuming all files from remote are selected by default
-                        status: 6,
-                    }
-                }else{
-                    FileData {
-                        name: rf.path.clone(),
-                        path: rf.path.clone(),
-                        select: true, // Assuming all files from remote are selected by default
-                        status: 5,
-                    }
-                }
-            }),
-    );
-
-    println!("{:?} {:?}", sync_info.folders, local_sync_info.folders);
-
-    // Handle status 8: Folder exists in sync_info.folders but not in local_sync_info.folders
-    result.extend(
-        sync_info.folders.iter()
-            .filter(|sf| Path::new(&path).join(Path::new(sf)).is_dir() && !local_sync_info.folders.contains(sf))
-            .map(|sf| {
-                FileData { name: sf.clone(), select: false, path: sf.clone(), status: 8 }
-            })
-    );
-
-    // Handle status 6: Folder exists in local_sync_info.folders but not in sync_info.folders
-    result.extend(
-        local_sync_info.folders.iter()
-            .filter(|lf| !sync_info.folders.contains(lf))
-            .map(|lf| {
-                FileData { name: lf.clone(), select: false, path: lf.clone(), status: 6 }
-            })
-    );
-
-    // Handle status 5: Folder exists in remote_sync_info.folders but not in sync_info.folders
-    result.extend(
-        remote_sync_info.folders.iter()
-            .filter(|rf| !local_sync_info.folders.contains(rf))
-            .map(|rf| {
-                FileData { name: rf.clone(), select: false, path: rf.clone(), status: 5 }
-            })
-    );
-
-    // Handle status 7: Folder exists in sync_info.folders but not in remote_sync_info.folders
-    result.extend(
-        local_sync_info.folders.iter()
-            .filter(|rf| !remote_sync_info.folders.contains(rf))
-            .map(|rf| {
-                FileData { name: rf.clone(), select: false, path: rf.clone(), status: 7 }
-            })
-    );
+#[tauri::command]
+async fn list_files_gd(window: tauri::Window, state: tauri::State<'_, Arc<MutexState>>, path: String, projectname: String, remote_drive: String) -> Result<Status, EntangleError> {
+    let remote = gd_remote(&state, &remote_drive, &projectname).await?;
 
-    Ok(result)
+    engine::status(&remote, Path::new(&path), &projectname, &emit_hash_progress(&window)).await.map_err(|e| log_error("Failed to list files", e))
 }
 
 fn read_sync_file(file_path: PathBuf) -> Result<SyncInfo, std::io::Error> {
@@ -612,295 +548,217 @@ fn read_sync_file(file_path: PathBuf) -> Result<SyncInfo, std::io::Error> {
 }
 
 #[tauri::command]
-fn commit(state: tauri::State<'_, Arc<MutexState>>, files: Vec<FileData>, commitmessage: String, remoteproject: String, remotepath: String, projectpath: String, projectname: String) -> bool {
-    let remote_sync_file_path = Path::new(&remotepath).join(format!("{}.sync", remoteproject));
-    let sync_file_path = Path::new(&projectpath).join(format!("{}.sync", projectname));
+async fn commit(window: tauri::Window, state: tauri::State<'_, Arc<MutexState>>, files: Vec<FileData>, revision: Option<String>, commitmessage: String, remoteproject: String, remotepath: String, projectpath: String, projectname: String, force: bool) -> Result<CommitOutcome, EntangleError> {
+    let remote = LocalFolderRemote::new(&remotepath, &remoteproject);
+    let author = signature(&state).await;
 
-    let existing_files: HashMap<String, ()> = WalkDir::new(&remotepath)
-        .into_iter()
-        .filter_map(|entry| entry.ok())
-        .filter(|entry| !entry.file_type().is_dir())
-        .map(|entry| {
-            let relative_path = entry.path().strip_prefix(&remotepath).unwrap();
-            (relative_path.to_string_lossy().into_owned(), ())
-        })
-        .collect();
-
-    // Copy each file from the local project to the remote path
-    for file_data in files.iter() {
-        if file_data.select {
-            let local_file_path = Path::new(&projectpath).join(&file_data.path);
-            let remote_file_path = Path::new(&remotepath).join(&file_data.path);
+    engine::commit(&remote, Path::new(&projectpath), &projectname, &files, revision, commitmessage, author, force, settings::load_settings().upload_concurrency, &emit_hash_progress(&window)).await.map_err(|e| log_error("Failed to commit", e))
+}
 
-            if(local_file_path.exists()){
-                let parres = remote_file_path.parent();
-                match parres {
-                    Some(v) => {
-                        fs::create_dir_all(v).unwrap();
-                    },
-                    None => {},
-                }
-                if let Err(e) = fs::copy(&local_file_path, &remote_file_path) {
-                    eprintln!("Failed to copy file: {} {}", parres.unwrap().to_str().unwrap().to_string(), e);
-                    return false;
-                }
-            }else {
-                if let Err(e) = fs::remove_file(&remote_file_path) {
-                    eprintln!("Failed to delete file: {}", e);
-                    return false;
-                }
-            }
-        }
-    }
+#[tauri::command]
+async fn gd_commit(window: tauri::Window, state: tauri::State<'_, Arc<MutexState>>, files: Vec<FileData>, revision: Option<String>, commitmessage: String, remoteid: String, projectpath: String, projectname: String, force: bool) -> Result<CommitOutcome, EntangleError> {
+    let remote = gd_remote(&state, &remoteid, &projectname).await?.with_upload_progress(emit_upload_progress(&window));
+    let author = signature(&state).await;
 
-    // Open and deserialize the remote .sync file
-    let mut remote_sync_info: SyncInfo = read_sync_file(remote_sync_file_path.clone()).unwrap();
+    engine::commit(&remote, Path::new(&projectpath), &projectname, &files, revision, commitmessage, author, force, settings::load_settings().upload_concurrency, &emit_hash_progress(&window)).await.map_err(|e| log_error("Failed to commit", e))
+}
 
-    // Set the msg field in SyncInfo to commitmessage
-    remote_sync_info.msg = commitmessage;
+// What a commit of the selected files would upload, for confirming before anything is sent
+#[tauri::command]
+async fn plan_commit(window: tauri::Window, files: Vec<FileData>, remoteproject: String, remotepath: String, projectpath: String, projectname: String) -> Result<CommitPlan, EntangleError> {
+    let remote = LocalFolderRemote::new(&remotepath, &remoteproject);
 
-    // Serialize SyncInfo to JSON
-    let remote_sync_info_json = serde_json::to_string_pretty(&remote_sync_info).unwrap();
+    engine::plan_commit(&remote, Path::new(&projectpath), &projectname, &files, &emit_hash_progress(&window)).await.map_err(|e| log_error("Failed to plan commit", e))
+}
 
-    // Save it back to the remote .sync file
-    if let Err(e) = write_sync_file(&remote_sync_file_path.clone(), &remote_sync_info_json) {
-        eprintln!("Failed to write to remote .sync file: {}", e);
-        return false;
-    }
+#[tauri::command]
+async fn plan_commit_gd(window: tauri::Window, state: tauri::State<'_, Arc<MutexState>>, files: Vec<FileData>, remoteid: String, projectpath: String, projectname: String) -> Result<CommitPlan, EntangleError> {
+    let remote = gd_remote(&state, &remoteid, &projectname).await?;
 
-    update_hashes(state.clone(), remotepath, remoteproject);
+    engine::plan_commit(&remote, Path::new(&projectpath), &projectname, &files, &emit_hash_progress(&window)).await.map_err(|e| log_error("Failed to plan commit", e))
+}
 
-    let resync = read_sync_file(remote_sync_file_path).unwrap();
-    if let Err(e) = write_sync_file(&sync_file_path.clone(), &serde_json::to_string_pretty(&resync).unwrap()) {
-        eprintln!("Failed to write to local .sync file: {}", e);
-        return false;
-    }
+#[tauri::command]
+async fn pull(files: Vec<FileData>, remoteproject: String, remotepath: String, projectpath: String, projectname: String) -> Result<bool, EntangleError> {
+    let remote = LocalFolderRemote::new(&remotepath, &remoteproject);
 
-    true
+    engine::pull(&remote, Path::new(&projectpath), &projectname, &files).await.map_err(|e| log_error("Failed to pull", e))?;
+    Ok(true)
 }
 
 #[tauri::command]
-async fn gd_commit(state: tauri::State<'_, Arc<MutexState>>, files: Vec<FileData>, commitmessage: String, remoteid: String, projectpath: String, projectname: String) -> Result<bool, ()> {
-    let mut lclstate: futures_util::lock::MutexGuard<'_, State> = state.inner().0.lock().await;
-    
-    let filelist: Vec<Box<Path>> = (&files).into_iter()
-        .filter(|entry| entry.status != 6)
-        .filter(|entry| !Path::new(&projectpath).join(Path::new(&entry.path)).is_dir())
-        .map(|f| {
-        Box::from(Path::new(&projectpath).join(&f.path))
-    }).collect();
-
-    let delfilelist: Vec<Box<Path>> = (&files).into_iter()
-        .filter(|entry| entry.status == 6)
-        .filter(|entry| !Path::new(&projectpath).join(Path::new(&entry.path)).is_dir())
-        .map(|f| {
-        Box::from(Path::new(&f.path))
-    }).collect();
+async fn gd_pull(state: tauri::State<'_, Arc<MutexState>>, files: Vec<FileData>, remoteid: String, projectpath: String, projectname: String) -> Result<bool, EntangleError> {
+    let remote = gd_remote(&state, &remoteid, &projectname).await?;
 
-    let folderlist: Vec<String> = (&files).into_iter()
-        .filter(|entry| entry.status != 6)
-        .filter(|entry| Path::new(&projectpath).join(Path::new(&entry.path)).is_dir())
-        .map(|f| {
-        f.path.clone()
-    }).collect();
+    engine::pull(&remote, Path::new(&projectpath), &projectname, &files).await.map_err(|e| log_error("Failed to pull", e))?;
+    Ok(true)
+}
 
-    update_hashes(state, projectpath.clone(), projectname.clone());
+// Settle an open conflict, see engine::resolve_conflict
+#[tauri::command]
+async fn resolve_conflict(projectpath: String, projectname: String, path: String, keep: Keep) -> Result<bool, EntangleError> {
+    // Hashing and copying a large part blocks, keep it off the main thread
+    tokio::task::block_in_place(|| engine::resolve_conflict(Path::new(&projectpath), &projectname, &path, keep))
+        .map_err(|e| log_error(&format!("Failed to resolve conflict for {}", path), e))?;
+    Ok(true)
+}
 
-    let sync_file_path = Path::new(&projectpath).join(format!("{}.sync", projectname));
+#[tauri::command]
+async fn list_history(remotepath: String, remoteproject: String) -> Result<Vec<Revision>, EntangleError> {
+    let remote = LocalFolderRemote::new(&remotepath, &remoteproject);
 
-    let sync_file = read_sync_file(sync_file_path.clone()).unwrap();
+    engine::list_history(&remote).await.map_err(|e| log_error("Failed to read history", e))
+}
 
-    let client = &lclstate.gdstruct.as_ref().unwrap().drive;
-    let tokens = &lclstate.gdstruct.as_ref().unwrap().token;
+#[tauri::command]
+async fn list_history_gd(state: tauri::State<'_, Arc<MutexState>>, remoteid: String, projectname: String) -> Result<Vec<Revision>, EntangleError> {
+    let remote = gd_remote(&state, &remoteid, &projectname).await?;
 
-    create_folder_gd(folderlist, remoteid.clone(), client, tokens).await;
+    engine::list_history(&remote).await.map_err(|e| log_error("Failed to read history", e))
+}
 
-    upload_files_to_google_drive(vec![Box::from(sync_file_path.clone())], &projectpath.clone(), &remoteid.clone(), client, tokens, Some(format!("{}.sync", projectname))).await;
+// Write a version of a file out of the remote object store into the project
+#[tauri::command]
+async fn restore_file(remotepath: String, remoteproject: String, projectpath: String, path: String, sha256: String) -> Result<bool, EntangleError> {
+    let remote = LocalFolderRemote::new(&remotepath, &remoteproject);
 
-    upload_files_to_google_drive(filelist, &projectpath.clone(), &remoteid.clone(), client, tokens, None).await;
+    engine::restore(&remote, Path::new(&projectpath), &path, &sha256).await.map_err(|e| log_error("Failed to restore file", e))?;
+    Ok(true)
+}
 
-    for item in delfilelist {
-        gd_delete_file(item.to_str().unwrap(), &remoteid, client, tokens).await;
-    }
+#[tauri::command]
+async fn restore_file_gd(state: tauri::State<'_, Arc<MutexState>>, remoteid: String, projectpath: String, projectname: String, path: String, sha256: String) -> Result<bool, EntangleError> {
+    let remote = gd_remote(&state, &remoteid, &projectname).await?;
 
+    engine::restore(&remote, Path::new(&projectpath), &path, &sha256).await.map_err(|e| log_error("Failed to restore file", e))?;
     Ok(true)
 }
 
-
 #[tauri::command]
-async fn gd_pull(state: tauri::State<'_, Arc<MutexState>>, files: Vec<FileData>, remoteid: String, projectpath: String, projectname: String) -> Result<bool, ()> {
-    let mut lclstate: futures_util::lock::MutexGuard<'_, State> = state.inner().0.lock().await;
-    
-    let client: &google_drive::Client = &lclstate.gdstruct.as_ref().unwrap().drive;
-    let tokens = &lclstate.gdstruct.as_ref().unwrap().token;
+async fn checkout_revision(window: tauri::Window, remotepath: String, remoteproject: String, projectpath: String, projectname: String, revision: String, apply: bool) -> Result<CheckoutPlan, EntangleError> {
+    let remote = LocalFolderRemote::new(&remotepath, &remoteproject);
 
-    let mut futures = Vec::new();
+    engine::checkout(&remote, Path::new(&projectpath), &projectname, &revision, apply, &emit_hash_progress(&window)).await.map_err(|e| log_error("Failed to check out revision", e))
+}
 
-    let semaphore = Arc::new(Semaphore::new(10));
+#[tauri::command]
+async fn checkout_revision_gd(window: tauri::Window, state: tauri::State<'_, Arc<MutexState>>, remoteid: String, projectpath: String, projectname: String, revision: String, apply: bool) -> Result<CheckoutPlan, EntangleError> {
+    let remote = gd_remote(&state, &remoteid, &projectname).await?;
 
-    let sync_file_path = Path::new(&projectpath).join(format!("{}.sync", projectname));
+    engine::checkout(&remote, Path::new(&projectpath), &projectname, &revision, apply, &emit_hash_progress(&window)).await.map_err(|e| log_error("Failed to check out revision", e))
+}
 
-    let sync_file = read_sync_file(sync_file_path.clone()).unwrap();
-    let syncfilearc = Arc::new(sync_file);
+#[tauri::command]
+async fn lock_file(state: tauri::State<'_, Arc<MutexState>>, remotepath: String, remoteproject: String, projectpath: String, path: String) -> Result<Lock, EntangleError> {
+    let remote = LocalFolderRemote::new(&remotepath, &remoteproject);
+    let author = signature(&state).await;
 
-    for f in files {
-        if f.status == 7 || f.status == 8 {
-            let filepath = Path::new(&projectpath).join(&f.path);
-            if filepath.is_dir() {
-                fs::remove_dir_all(filepath).unwrap();
-            }else{
-                fs::remove_file(filepath).unwrap();
-            }
-        }else{
-            let permit = semaphore.clone().acquire_owned().await.unwrap();
-            let lclremoteid = remoteid.clone();
-            let lclprojectpath = projectpath.clone();
-            let lclclient = client.clone();
-            let lcltoken = tokens.clone();
-
-            let syncarc = syncfilearc.clone();
-            futures.push(tokio::spawn(async move {
-                let data = gd_get_file(&f.path, &lclremoteid, &lclclient, &lcltoken).await;
-                let filepath = Path::new(&lclprojectpath).join(&f.path);
-                
-                let parres = filepath.parent();
-                match parres {
-                    Some(v) => {
-                        let _ = fs::create_dir_all(v);
-                    },
-                    None => {},
-                }
+    engine::lock(&remote, Path::new(&projectpath), &path, author.name, author.email).await.map_err(|e| log_error("Failed to lock file", e))
+}
 
-                println!("{:?}", filepath.as_os_str());
+#[tauri::command]
+async fn lock_file_gd(state: tauri::State<'_, Arc<MutexState>>, remoteid: String, projectpath: String, projectname: String, path: String) -> Result<Lock, EntangleError> {
+    let remote = gd_remote(&state, &remoteid, &projectname).await?;
+    let author = signature(&state).await;
 
-                match data {
-                    Some(v) => {
-                        let mut fl = File::create(filepath).unwrap();
-                        fl.write_all(&v).unwrap();
-                    },
-                    None => {
-                        fs::create_dir_all(filepath).unwrap();
-                    },
-                }
-                drop(permit);
-            }));
-        }
-    }
-    let lclremoteid = remoteid.clone();
-    let lclprojectpath = projectpath.clone();
-    let lclclient = client.clone();
-    let lcltoken = tokens.clone();
-    let path = format!("{}.sync", projectname);
-
-    let sync_file2 = read_sync_file(sync_file_path.clone()).unwrap();
-
-    let data = gd_get_file(&path, &lclremoteid, &lclclient, &lcltoken).await.unwrap();
-    let filepath = Path::new(&lclprojectpath).join(path);
-    
-    let parres = filepath.parent();
-    match parres {
-        Some(v) => {
-            let _ = fs::create_dir_all(v);
-        },
-        None => {},
-    }
+    engine::lock(&remote, Path::new(&projectpath), &path, author.name, author.email).await.map_err(|e| log_error("Failed to lock file", e))
+}
+
+#[tauri::command]
+async fn unlock_file(state: tauri::State<'_, Arc<MutexState>>, remotepath: String, remoteproject: String, projectpath: String, path: String, force: bool, readonly: bool) -> Result<bool, EntangleError> {
+    let remote = LocalFolderRemote::new(&remotepath, &remoteproject);
+    let author = signature(&state).await;
 
-    let mut fl = File::create(filepath).unwrap();
-    fl.write_all(&data).unwrap();
+    engine::unlock(&remote, Path::new(&projectpath), &path, &author.email, force, readonly).await.map_err(|e| log_error("Failed to unlock file", e))?;
+    Ok(true)
+}
 
-    let singlefut = futures_util::future::join_all(futures);
-    singlefut.await;
+#[tauri::command]
+async fn unlock_file_gd(state: tauri::State<'_, Arc<MutexState>>, remoteid: String, projectpath: String, projectname: String, path: String, force: bool, readonly: bool) -> Result<bool, EntangleError> {
+    let remote = gd_remote(&state, &remoteid, &projectname).await?;
+    let author = signature(&state).await;
 
+    engine::unlock(&remote, Path::new(&projectpath), &path, &author.email, force, readonly).await.map_err(|e| log_error("Failed to unlock file", e))?;
     Ok(true)
 }
 
 fn write_sync_file(file_path: &Path, content: &str) -> Result<(), std::io::Error> {
-    let mut file = File::create(file_path)?;
-    file.write_all(content.as_bytes())?;
-    Ok(())
+    write_atomic(file_path, content.as_bytes())
 }
 
-#[tauri::command]
-fn validate_gsfile(state: tauri::State<'_, Arc<MutexState>>, id: String) -> bool {
-    return false;
+// Write under a temporary name next to file_path and rename it into place, so nobody ever reads a half written file.
+// The temporary name is unique, two people writing the same file on a shared folder never write into the same one
+fn write_atomic(file_path: &Path, content: &[u8]) -> Result<(), std::io::Error> {
+    let mut tmp_name = file_path.file_name().unwrap_or_default().to_os_string();
+    tmp_name.push(format!(".{}.tmp", uuid::Uuid::new_v4()));
+    let tmp = file_path.with_file_name(tmp_name);
+
+    let result = File::create(&tmp)
+        .and_then(|mut file| file.write_all(content).and_then(|_| file.sync_all()))
+        .and_then(|_| fs::rename(&tmp, file_path));
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp);
+    }
+    result
 }
 
 #[tauri::command]
-fn push(state: tauri::State<'_, Arc<MutexState>>, projectname: String, remoteid: String) -> bool {
-    return false;
+async fn get_fw_files(projectpath: String) -> Result<Vec<String>, EntangleError> {
+    list_fw_files(projectpath).await.map_err(|e| log_error("Failed to list files for Fabworks", e))
 }
 
 #[tauri::command]
-async fn get_fw_files(projectpath: String) -> Vec<String> {
-    list_fw_files(projectpath).await
+async fn send_to_fw(files: Vec<String>, projectpath: String) -> Result<bool, EntangleError> {
+    push_to_fw(projectpath, files).await.map_err(|e| log_error("Failed to send files to Fabworks", e))?;
+    Ok(true)
 }
 
 #[tauri::command]
-async fn send_to_fw(files: Vec<String>, projectpath: String) -> Result<bool, ()> {
-    push_to_fw(projectpath, files).await.unwrap();
-    return Ok(true);
-}
+async fn gd_get_sync_file(state: tauri::State<'_, Arc<MutexState>>, driveid: String) -> Result<String, EntangleError> {
+    let session = gd_session(&state).await?;
 
-#[tauri::command]
-async fn gd_get_sync_file(state: tauri::State<'_, Arc<MutexState>>, driveid: String) -> Result<String, ()> {
-    let mut lclstate: futures_util::lock::MutexGuard<'_, State> = state.inner().0.lock().await;
-    
-    let client = &lclstate.gdstruct.as_ref().unwrap().drive;
-    let tokens = &lclstate.gdstruct.as_ref().unwrap().token;
+    gd_get_sync(&driveid, &session).await.map_err(|e| log_error("Failed to find the project's sync file", e))
+}
 
-    Ok(gd_get_sync(&driveid, client, tokens).await)
+fn savefile_path() -> Result<PathBuf, EntangleError> {
+    dirs::config_dir()
+        .map(|v| v.join("Entangle").join("savefile.json"))
+        .ok_or_else(|| EntangleError::Io("Couldn't find the config folder".to_string()))
 }
 
 #[tauri::command]
-async fn save_proj(driveid: String, syncfile: String, name: String){
-    let pth = dirs::config_dir().unwrap().join("Entangle").join("savefile.json");
-    if pth.exists() {
-        let mut file = File::options()
-            .read(true)
-            .open(&pth).unwrap();
-        let reader = std::io::BufReader::new(&file);
-        let mut sync_info: SaveFile = serde_json::from_reader(reader).unwrap();
-
-        sync_info.saves.push(Save {
-            name,
-            driveid,
-            folderpath: syncfile,
-            lastaccessed: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis()
-        });
-
-        file = File::create(pth).unwrap();
-
-        file.write_all(serde_json::to_string(&sync_info).unwrap().as_bytes()).unwrap();
-    }else{
-        fs::create_dir_all(&pth.parent().unwrap()).unwrap();
-        let mut file = File::create(pth).unwrap();
-        let sync_info: SaveFile = SaveFile{
-            saves: vec![Save {
-                name,
-                driveid,
-                folderpath: syncfile,
-                lastaccessed: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis()
-            }]
-        };
-        file.write_all(serde_json::to_string(&sync_info).unwrap().as_bytes()).unwrap();
+async fn save_proj(driveid: String, syncfile: String, name: String) -> Result<(), EntangleError> {
+    let pth = savefile_path()?;
+    let mut sync_info = read_savefile(&pth)?;
+
+    sync_info.saves.push(Save {
+        name,
+        driveid,
+        folderpath: syncfile,
+        lastaccessed: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis()
+    });
+
+    if let Some(parent) = pth.parent() {
+        fs::create_dir_all(parent)?;
     }
+    let mut file = File::create(pth)?;
+    file.write_all(serde_json::to_string(&sync_info)?.as_bytes())?;
+    Ok(())
 }
 
 #[tauri::command]
-async fn get_projs() -> SaveFile{
-    let pth = dirs::config_dir().unwrap().join("Entangle").join("savefile.json");
-    if pth.exists() {
-        let mut file = File::options()
-            .read(true)
-            .write(false)
-            .open(pth).unwrap();
-        let reader = std::io::BufReader::new(&file);
-        let mut sync_info: SaveFile = serde_json::from_reader(reader).unwrap();
+async fn get_projs() -> Result<SaveFile, EntangleError> {
+    read_savefile(&savefile_path()?)
+}
 
-        return sync_info;
-    }else{
-        return SaveFile{
+fn read_savefile(pth: &Path) -> Result<SaveFile, EntangleError> {
+    if !pth.exists() {
+        return Ok(SaveFile{
             saves: Vec::new()
-        }
+        });
     }
-}
\ No newline at end of file
+
+    let file = File::open(pth)?;
+    let reader = std::io::BufReader::new(&file);
+    serde_json::from_reader(reader).map_err(|e| log_error("Failed to read saved projects", EntangleError::Manifest(e.to_string())))
+}
@@ -0,0 +1,340 @@
+use std::{collections::HashMap, convert::Infallible, net::SocketAddr, sync::{Arc, Mutex}};
+
+use google_drive::AccessToken;
+use hyper::{Body, Method, Request, Response, Server, StatusCode, header, service::{make_service_fn, service_fn}};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use url::form_urlencoded;
+
+use crate::auth::GDStruct;
+use crate::authconfig::AuthProvider;
+use crate::gdhttp::DriveSession;
+
+const FOLDER_MIME_TYPE: &str = "application/vnd.google-apps.folder";
+
+// The id of the folder every test project lives in
+pub(crate) const ROOT_ID: &str = "root";
+
+#[derive(Clone)]
+struct MockFile {
+    id: String,
+    name: String,
+    parent: String,
+    mime_type: String,
+    contents: Vec<u8>,
+    trashed: bool,
+}
+
+// A resumable upload in progress
+struct MockUpload {
+    // The file being replaced, or None for a new one
+    target: Option<String>,
+    name: String,
+    parent: String,
+    total: u64,
+    received: Vec<u8>,
+    done: Option<String>,
+}
+
+#[derive(Default)]
+struct MockState {
+    base_url: String,
+    files: Vec<MockFile>,
+    next_id: usize,
+    uploads: Vec<MockUpload>,
+    // Every request made, as "METHOD /path"
+    requests: Vec<String>,
+    // Where each upload chunk started
+    chunk_starts: Vec<u64>,
+    // Chunks starting at or past this offset fail, like a dropped connection would
+    fail_chunks_from: Option<u64>,
+}
+
+impl MockState {
+    fn add(&mut self, parent: &str, name: &str, mime_type: &str, contents: Vec<u8>) -> String {
+        self.next_id += 1;
+        let id = format!("id{}", self.next_id);
+        self.files.push(MockFile {
+            id: id.clone(),
+            name: name.to_string(),
+            parent: parent.to_string(),
+            mime_type: mime_type.to_string(),
+            contents,
+            trashed: false,
+        });
+        id
+    }
+}
+
+// Just enough of the Drive API on a loopback port to run the Drive remote against: searching, downloading,
+// creating folders, resumable uploads, copying and deleting. Keeps a log of every request it gets
+pub(crate) struct MockDrive {
+    addr: SocketAddr,
+    state: Arc<Mutex<MockState>>,
+}
+
+impl MockDrive {
+    pub async fn start() -> MockDrive {
+        let state = Arc::new(Mutex::new(MockState::default()));
+
+        let service_state = state.clone();
+        let make_svc = make_service_fn(move |_conn| {
+            let state = service_state.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |req| handle(req, state.clone())))
+            }
+        });
+
+        let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_svc);
+        let addr = server.local_addr();
+        state.lock().unwrap().base_url = format!("http://{}", addr);
+        tokio::spawn(server);
+
+        MockDrive { addr, state }
+    }
+
+    // A logged in session that talks to this server instead of Google
+    pub fn session(&self) -> DriveSession {
+        let token = AccessToken {
+            access_token: "mock".to_string(),
+            refresh_token: "mock".to_string(),
+            ..Default::default()
+        };
+        let gds = GDStruct::new(token, Some("mock@example.com".to_string()), u64::MAX, AuthProvider::default());
+        DriveSession::new(gds).with_api_root(&format!("http://{}", self.addr))
+    }
+
+    pub fn add_folder(&self, parent: &str, name: &str) -> String {
+        self.state.lock().unwrap().add(parent, name, FOLDER_MIME_TYPE, Vec::new())
+    }
+
+    pub fn add_file(&self, parent: &str, name: &str, contents: &[u8]) -> String {
+        self.state.lock().unwrap().add(parent, name, "application/octet-stream", contents.to_vec())
+    }
+
+    // Move a file to the trash, where Drive still serves it by its id
+    pub fn trash(&self, id: &str) {
+        if let Some(f) = self.state.lock().unwrap().files.iter_mut().find(|f| f.id == id) {
+            f.trashed = true;
+        }
+    }
+
+    // The contents of the file called name, if there is one
+    pub fn contents(&self, name: &str) -> Option<Vec<u8>> {
+        self.state.lock().unwrap().files.iter().find(|f| f.name == name).map(|f| f.contents.clone())
+    }
+
+    pub fn fail_chunks_from(&self, offset: Option<u64>) {
+        self.state.lock().unwrap().fail_chunks_from = offset;
+    }
+
+    pub fn chunk_starts(&self) -> Vec<u64> {
+        self.state.lock().unwrap().chunk_starts.clone()
+    }
+
+    pub fn requests(&self) -> Vec<String> {
+        self.state.lock().unwrap().requests.clone()
+    }
+
+    // Requests that would have added something to the drive
+    pub fn creates(&self) -> Vec<String> {
+        self.requests().into_iter().filter(|r| r.starts_with("POST ")).collect()
+    }
+
+    pub fn folder_names(&self) -> Vec<String> {
+        self.state.lock().unwrap().files.iter()
+            .filter(|f| f.mime_type == FOLDER_MIME_TYPE)
+            .map(|f| f.name.clone())
+            .collect()
+    }
+}
+
+fn file_json(f: &MockFile) -> serde_json::Value {
+    json!({
+        "id": f.id,
+        "name": f.name,
+        "mimeType": f.mime_type,
+        "parents": [f.parent],
+        "trashed": f.trashed,
+        "size": f.contents.len().to_string(),
+        "sha256Checksum": format!("{:x}", Sha256::digest(&f.contents)),
+    })
+}
+
+fn respond(status: StatusCode, body: Body) -> Result<Response<Body>, Infallible> {
+    let mut response = Response::new(body);
+    *response.status_mut() = status;
+    Ok(response)
+}
+
+// Whether f matches a search like "name = 'a' and mimeType = '...' and 'id' in parents and trashed = false"
+fn matches_query(f: &MockFile, query: &str) -> bool {
+    query.split(" and ").all(|condition| {
+        let condition = condition.trim();
+        let quoted = condition.split('\'').nth(1).unwrap_or_default();
+
+        if condition.starts_with("name = ") {
+            f.name == quoted
+        }else if condition.starts_with("name contains ") {
+            f.name.contains(quoted)
+        }else if condition.starts_with("mimeType = ") {
+            f.mime_type == quoted
+        }else if condition.ends_with(" in parents") {
+            f.parent == quoted
+        }else if condition.starts_with("trashed = ") {
+            !f.trashed
+        }else{
+            true
+        }
+    })
+}
+
+async fn handle(req: Request<Body>, state: Arc<Mutex<MockState>>) -> Result<Response<Body>, Infallible> {
+    let method = req.method().clone();
+    let path = req.uri().path().to_string();
+    let params: HashMap<String, String> = form_urlencoded::parse(req.uri().query().unwrap_or_default().as_bytes())
+        .into_owned()
+        .collect();
+    let content_range = req.headers().get(header::CONTENT_RANGE).and_then(|v| v.to_str().ok()).unwrap_or_default().to_string();
+    let upload_length = req.headers().get("X-Upload-Content-Length").and_then(|v| v.to_str().ok()).and_then(|v| v.parse().ok()).unwrap_or(0);
+    let body = hyper::body::to_bytes(req.into_body()).await.unwrap_or_default();
+
+    let mut state = state.lock().unwrap();
+    state.requests.push(format!("{} {}", method, path));
+
+    let id = path.strip_prefix("/drive/v3/files/").map(|v| v.to_string());
+
+    match (method, id) {
+        (Method::GET, None) if path == "/drive/v3/files" => {
+            let query = params.get("q").cloned().unwrap_or_default();
+            let files: Vec<serde_json::Value> = state.files.iter()
+                .filter(|f| matches_query(f, &query))
+                .map(file_json)
+                .collect();
+            respond(StatusCode::OK, Body::from(json!({ "files": files }).to_string()))
+        }
+        (Method::GET, Some(id)) => match state.files.iter().find(|f| f.id == id) {
+            Some(f) if params.get("alt").map(|v| v.as_str()) == Some("media") => respond(StatusCode::OK, Body::from(f.contents.clone())),
+            Some(f) => respond(StatusCode::OK, Body::from(file_json(f).to_string())),
+            None => respond(StatusCode::NOT_FOUND, Body::empty()),
+        },
+        // Only folders get created through the plain files endpoint, uploads go through /upload
+        (Method::POST, None) if path == "/drive/v3/files" => {
+            let file: serde_json::Value = serde_json::from_slice(&body).unwrap_or_default();
+            let name = file["name"].as_str().unwrap_or_default().to_string();
+            let mime_type = file["mimeType"].as_str().unwrap_or_default().to_string();
+            let parent = file["parents"][0].as_str().unwrap_or_default().to_string();
+
+            let id = state.add(&parent, &name, &mime_type, Vec::new());
+            let created = state.files.iter().find(|f| f.id == id).unwrap().clone();
+            respond(StatusCode::OK, Body::from(file_json(&created).to_string()))
+        }
+        (Method::POST, Some(id)) if id.ends_with("/copy") => {
+            let original = match state.files.iter().find(|f| f.id == id.trim_end_matches("/copy")) {
+                Some(f) => f.clone(),
+                None => return respond(StatusCode::NOT_FOUND, Body::empty()),
+            };
+            let file: serde_json::Value = serde_json::from_slice(&body).unwrap_or_default();
+            let name = file["name"].as_str().unwrap_or(&original.name).to_string();
+            let parent = file["parents"][0].as_str().unwrap_or(&original.parent).to_string();
+
+            let id = state.add(&parent, &name, &original.mime_type, original.contents);
+            let copy = state.files.iter().find(|f| f.id == id).unwrap().clone();
+            respond(StatusCode::OK, Body::from(file_json(&copy).to_string()))
+        }
+        (Method::POST, None) if path == "/upload/drive/v3/files" => {
+            let file: serde_json::Value = serde_json::from_slice(&body).unwrap_or_default();
+            let name = file["name"].as_str().unwrap_or_default().to_string();
+            let parent = file["parents"][0].as_str().unwrap_or_default().to_string();
+            start_upload(&mut state, None, name, parent, upload_length)
+        }
+        (Method::PATCH, None) if path.starts_with("/upload/drive/v3/files/") => {
+            let id = path.trim_start_matches("/upload/drive/v3/files/").to_string();
+            let existing = match state.files.iter().find(|f| f.id == id) {
+                Some(f) => f.clone(),
+                None => return respond(StatusCode::NOT_FOUND, Body::empty()),
+            };
+
+            if params.get("uploadType").map(|v| v.as_str()) == Some("resumable") {
+                start_upload(&mut state, Some(id), existing.name, existing.parent, upload_length)
+            }else{
+                state.files.iter_mut().find(|f| f.id == id).unwrap().contents = body.to_vec();
+                respond(StatusCode::OK, Body::from(file_json(&existing).to_string()))
+            }
+        }
+        (Method::PUT, None) if path.starts_with("/upload/session/") => {
+            let n: usize = path.trim_start_matches("/upload/session/").parse().unwrap_or(usize::MAX);
+            upload_chunk(&mut state, n, &content_range, body.to_vec())
+        }
+        (Method::DELETE, Some(id)) => {
+            let before = state.files.len();
+            state.files.retain(|f| f.id != id);
+            if state.files.len() == before {
+                respond(StatusCode::NOT_FOUND, Body::empty())
+            }else{
+                respond(StatusCode::NO_CONTENT, Body::empty())
+            }
+        }
+        _ => respond(StatusCode::NOT_IMPLEMENTED, Body::empty()),
+    }
+}
+
+fn start_upload(state: &mut MockState, target: Option<String>, name: String, parent: String, total: u64) -> Result<Response<Body>, Infallible> {
+    state.uploads.push(MockUpload { target, name, parent, total, received: Vec::new(), done: None });
+    let uri = format!("{}/upload/session/{}", state.base_url, state.uploads.len() - 1);
+
+    let mut response = Response::new(Body::empty());
+    response.headers_mut().insert(header::LOCATION, uri.parse().unwrap());
+    Ok(response)
+}
+
+// A chunk sent to upload n, or with a range of "bytes */total" a question about how much of it has arrived
+fn upload_chunk(state: &mut MockState, n: usize, content_range: &str, chunk: Vec<u8>) -> Result<Response<Body>, Infallible> {
+    if n >= state.uploads.len() {
+        return respond(StatusCode::NOT_FOUND, Body::empty());
+    }
+
+    let range = content_range.trim_start_matches("bytes ");
+    if let Some((start, _)) = range.split_once('-') {
+        let start: u64 = start.parse().unwrap_or(0);
+        if state.fail_chunks_from.map_or(false, |v| start >= v) {
+            return respond(StatusCode::SERVICE_UNAVAILABLE, Body::empty());
+        }
+        state.chunk_starts.push(start);
+
+        let upload = &mut state.uploads[n];
+        if start != upload.received.len() as u64 {
+            return respond(StatusCode::BAD_REQUEST, Body::empty());
+        }
+        upload.received.extend(chunk);
+    }
+
+    let upload = &state.uploads[n];
+    if let Some(id) = &upload.done {
+        let file = state.files.iter().find(|f| &f.id == id).unwrap();
+        return respond(StatusCode::OK, Body::from(file_json(file).to_string()));
+    }
+
+    if (upload.received.len() as u64) < upload.total {
+        let mut response = Response::new(Body::empty());
+        *response.status_mut() = StatusCode::PERMANENT_REDIRECT;
+        if !upload.received.is_empty() {
+            let received = format!("bytes=0-{}", upload.received.len() - 1);
+            response.headers_mut().insert(header::RANGE, received.parse().unwrap());
+        }
+        return Ok(response);
+    }
+
+    let (target, name, parent, contents) = (upload.target.clone(), upload.name.clone(), upload.parent.clone(), upload.received.clone());
+    let id = match target {
+        Some(id) => {
+            state.files.iter_mut().find(|f| f.id == id).unwrap().contents = contents;
+            id
+        }
+        None => state.add(&parent, &name, "application/octet-stream", contents),
+    };
+    state.uploads[n].done = Some(id.clone());
+
+    let file = state.files.iter().find(|f| f.id == id).unwrap();
+    respond(StatusCode::OK, Body::from(file_json(file).to_string()))
+}
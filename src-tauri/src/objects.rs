@@ -0,0 +1,109 @@
+use std::{path::{Path, PathBuf}, fs, collections::HashSet};
+
+use crate::compute_sha256;
+use crate::hashcache::{Fingerprint, HashCache};
+
+// Entangle's own bookkeeping lives in this folder at the root of a project or remote, and is never synced
+pub(crate) const META_DIR: &str = ".entangle";
+// Every file version ever committed, stored under its sha256
+pub(crate) const OBJECTS_DIR: &str = ".entangle/objects";
+
+pub(crate) fn object_path(root: &Path, sha256: &str) -> PathBuf {
+    root.join(OBJECTS_DIR).join(sha256)
+}
+
+// Copy a file into the object store of root. Objects are immutable, so an existing one is left alone
+pub(crate) fn store_object(root: &Path, file_path: &Path, sha256: &str) -> Result<(), std::io::Error> {
+    let dest = object_path(root, sha256);
+    if dest.is_file() {
+        return Ok(());
+    }
+
+    fs::create_dir_all(root.join(OBJECTS_DIR))?;
+
+    // A half written object never shows up under its hash. Two files with the same contents can be staged at once,
+    // whichever lands second finds the object already there
+    match copy_atomic(file_path, &dest) {
+        Err(_) if dest.is_file() => Ok(()),
+        result => result,
+    }
+}
+
+// Copy file_path to dest under a temporary name and rename it into place, so dest is only ever the old file or the
+// whole new one. The temporary name is unique, copies running side by side don't write into each other's
+pub(crate) fn copy_atomic(file_path: &Path, dest: &Path) -> Result<(), std::io::Error> {
+    let mut tmp_name = dest.file_name().unwrap_or_default().to_os_string();
+    tmp_name.push(format!(".{}.tmp", uuid::Uuid::new_v4()));
+    let tmp = dest.with_file_name(tmp_name);
+
+    if let Err(e) = fs::copy(file_path, &tmp).and_then(|_| fs::rename(&tmp, dest)) {
+        let _ = fs::remove_file(&tmp);
+        return Err(e);
+    }
+    Ok(())
+}
+
+// Stash whatever is currently at file_path in the object store before it gets overwritten or deleted
+pub(crate) fn preserve_file(root: &Path, file_path: &Path) -> Result<(), std::io::Error> {
+    if !file_path.is_file() {
+        return Ok(());
+    }
+    let sha256 = compute_sha256(file_path)?;
+    store_object(root, file_path, &sha256)
+}
+
+// Stashes local files before a pull or checkout replaces them. Contents the remote already holds can be fetched from
+// there again, so only versions it has never seen are copied. Hashes come from the project's hash cache where it
+// still matches the file
+pub(crate) struct Stash {
+    root: PathBuf,
+    cache: HashCache,
+    known: HashSet<String>,
+}
+
+impl Stash {
+    // known is the sha256 of every version the remote has in its object store, as far as the caller can tell
+    pub fn new(root: &Path, known: impl IntoIterator<Item = String>) -> Stash {
+        Stash {
+            root: root.to_path_buf(),
+            cache: HashCache::load(root),
+            known: known.into_iter().collect(),
+        }
+    }
+
+    pub fn preserve(&self, file_path: &Path) -> Result<(), std::io::Error> {
+        if !file_path.is_file() {
+            return Ok(());
+        }
+
+        let relative_path = file_path.strip_prefix(&self.root).unwrap_or(file_path).to_string_lossy();
+        let sha256 = match self.cache.lookup(&relative_path, &Fingerprint::of(&fs::metadata(file_path)?)) {
+            Some(v) => v,
+            None => compute_sha256(file_path)?,
+        };
+        if self.known.contains(&sha256) {
+            return Ok(());
+        }
+        store_object(&self.root, file_path, &sha256)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testproject::{TestDir, sha256_of};
+
+    #[test]
+    fn only_versions_the_remote_does_not_have_are_stashed() {
+        let project = TestDir::new();
+        project.write("bracket.sldprt", b"bracket");
+        project.write("plate.sldprt", b"plate");
+        let stash = Stash::new(&project.path, vec![sha256_of(b"bracket")]);
+
+        stash.preserve(&project.path.join("bracket.sldprt")).unwrap();
+        stash.preserve(&project.path.join("plate.sldprt")).unwrap();
+
+        assert!(!object_path(&project.path, &sha256_of(b"bracket")).exists());
+        assert_eq!(fs::read(object_path(&project.path, &sha256_of(b"plate"))).unwrap(), b"plate");
+    }
+}
@@ -0,0 +1,151 @@
+use std::{path::{Path, PathBuf}, fs, io::{Error, ErrorKind}};
+
+use async_trait::async_trait;
+use walkdir::WalkDir;
+
+use crate::{SyncInfo, read_sync_file, write_sync_file};
+use crate::history::{History, Revision, read_history, read_revision, revision_path, write_revision};
+use crate::lock::{Locks, read_locks, write_locks};
+use crate::objects::{META_DIR, copy_atomic, object_path, store_object, preserve_file};
+
+// Somewhere a project is synced to. Paths are relative to the root of the project, objects are keyed by sha256
+#[async_trait]
+pub(crate) trait Remote: Send + Sync {
+    async fn read_manifest(&self) -> Result<SyncInfo, Error>;
+    async fn write_manifest(&self, manifest: &SyncInfo) -> Result<(), Error>;
+
+    // A NotFound error for a revision that isn't stored on its own, it may still be in the legacy history
+    async fn read_revision(&self, id: &str) -> Result<Revision, Error>;
+    async fn write_revision(&self, revision: &Revision) -> Result<(), Error>;
+    // The single history file revisions used to be kept in, empty if there never was one
+    async fn read_history(&self) -> Result<History, Error>;
+
+    async fn read_locks(&self) -> Result<Locks, Error>;
+    async fn write_locks(&self, locks: &Locks) -> Result<(), Error>;
+
+    // The current version of a file, as seen by anyone browsing the remote
+    async fn get_blob(&self, path: &str) -> Result<Vec<u8>, Error>;
+    // Make the file at path a copy of an object already in the object store, without sending the contents again
+    async fn copy_object(&self, sha256: &str, path: &str) -> Result<(), Error>;
+    async fn create_folder(&self, path: &str) -> Result<(), Error>;
+    async fn delete(&self, path: &str) -> Result<(), Error>;
+    // Every file anyone browsing the remote would see, committed or not, leaving out Entangle's own
+    async fn list(&self) -> Result<Vec<String>, Error>;
+
+    async fn get_object(&self, sha256: &str) -> Result<Vec<u8>, Error>;
+    // path is the project file the object holds the contents of
+    async fn put_object(&self, sha256: &str, path: &str, local_file: &Path) -> Result<(), Error>;
+    // Whether the remote holds all size bytes of the object, asked of the remote itself rather than any cache
+    async fn verify_object(&self, sha256: &str, size: u64) -> Result<bool, Error>;
+
+    // The account the remote is accessed as, if it has one
+    fn account(&self) -> Option<String> {
+        None
+    }
+}
+
+// A plain folder, usually a shared network drive
+pub(crate) struct LocalFolderRemote {
+    root: PathBuf,
+    project: String,
+}
+
+impl LocalFolderRemote {
+    pub fn new(root: &str, project: &str) -> LocalFolderRemote {
+        LocalFolderRemote {
+            root: PathBuf::from(root),
+            project: project.to_string(),
+        }
+    }
+}
+
+#[async_trait]
+impl Remote for LocalFolderRemote {
+    async fn read_manifest(&self) -> Result<SyncInfo, Error> {
+        read_sync_file(self.root.join(format!("{}.sync", self.project)))
+    }
+
+    async fn write_manifest(&self, manifest: &SyncInfo) -> Result<(), Error> {
+        write_sync_file(&self.root.join(format!("{}.sync", self.project)), &serde_json::to_string_pretty(manifest).unwrap())
+    }
+
+    async fn read_revision(&self, id: &str) -> Result<Revision, Error> {
+        read_revision(&self.root.join(revision_path(id)))
+    }
+
+    async fn write_revision(&self, revision: &Revision) -> Result<(), Error> {
+        write_revision(&self.root.join(revision_path(&revision.id)), revision)
+    }
+
+    async fn read_history(&self) -> Result<History, Error> {
+        read_history(&self.root.join(format!("{}.history", self.project)))
+    }
+
+    async fn read_locks(&self) -> Result<Locks, Error> {
+        read_locks(&self.root.join(format!("{}.locks", self.project)))
+    }
+
+    async fn write_locks(&self, locks: &Locks) -> Result<(), Error> {
+        write_locks(&self.root.join(format!("{}.locks", self.project)), locks)
+    }
+
+    async fn get_blob(&self, path: &str) -> Result<Vec<u8>, Error> {
+        fs::read(self.root.join(path))
+    }
+
+    async fn copy_object(&self, sha256: &str, path: &str) -> Result<(), Error> {
+        let dest = self.root.join(path);
+
+        // The version being replaced doesn't need stashing, commit put it in the object store when it went in
+        if let Some(parent) = dest.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        copy_atomic(&object_path(&self.root, sha256), &dest)
+    }
+
+    async fn create_folder(&self, path: &str) -> Result<(), Error> {
+        fs::create_dir_all(self.root.join(path))
+    }
+
+    async fn delete(&self, path: &str) -> Result<(), Error> {
+        let dest = self.root.join(path);
+
+        if dest.is_dir() {
+            fs::remove_dir_all(dest)
+        }else{
+            preserve_file(&self.root, &dest)?;
+            fs::remove_file(dest)
+        }
+    }
+
+    async fn list(&self) -> Result<Vec<String>, Error> {
+        let own = [format!("{}.sync", self.project), format!("{}.history", self.project), format!("{}.locks", self.project)];
+        let mut result = Vec::new();
+
+        // Skip Entangle's own bookkeeping
+        for entry in WalkDir::new(&self.root).into_iter().filter_entry(|entry| entry.file_name() != META_DIR) {
+            let entry = entry?;
+            let relative_path = entry.path().strip_prefix(&self.root).unwrap().to_string_lossy().into_owned();
+            if !entry.file_type().is_dir() && !own.contains(&relative_path) {
+                result.push(relative_path);
+            }
+        }
+        Ok(result)
+    }
+
+    async fn get_object(&self, sha256: &str) -> Result<Vec<u8>, Error> {
+        fs::read(object_path(&self.root, sha256))
+    }
+
+    async fn put_object(&self, sha256: &str, _path: &str, local_file: &Path) -> Result<(), Error> {
+        store_object(&self.root, local_file, sha256)
+    }
+
+    async fn verify_object(&self, sha256: &str, size: u64) -> Result<bool, Error> {
+        match fs::metadata(object_path(&self.root, sha256)) {
+            Ok(meta) => Ok(meta.len() == size),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+}
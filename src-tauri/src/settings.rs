@@ -0,0 +1,56 @@
+use std::{fs::{self, File}, io::{Error, Write}, path::PathBuf};
+
+use serde::{Serialize, Deserialize};
+
+use crate::error::EntangleError;
+
+// Preferences from the Settings dialog, apart from how to log in
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub(crate) struct Settings {
+    // How many files a commit uploads at once
+    pub upload_concurrency: usize,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings { upload_concurrency: 4 }
+    }
+}
+
+fn settings_path() -> Result<PathBuf, EntangleError> {
+    dirs::config_dir()
+        .map(|v| v.join("Entangle").join("settings.json"))
+        .ok_or_else(|| EntangleError::Io("Couldn't find the config folder".to_string()))
+}
+
+pub(crate) fn load_settings() -> Settings {
+    let pth = match settings_path() {
+        Ok(v) => v,
+        Err(e) => {
+            eprintln!("{}", e);
+            return Settings::default();
+        }
+    };
+    if !pth.exists() {
+        return Settings::default();
+    }
+
+    match fs::read(&pth).map_err(|e| e.to_string()).and_then(|v| serde_json::from_slice(&v).map_err(|e| e.to_string())) {
+        Ok(v) => v,
+        Err(e) => {
+            eprintln!("Failed to read {}: {}", pth.display(), e);
+            Settings::default()
+        }
+    }
+}
+
+pub(crate) fn save_settings(settings: &Settings) -> Result<(), Error> {
+    let pth = settings_path()?;
+    if let Some(parent) = pth.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut file = File::create(pth)?;
+    file.write_all(serde_json::to_string_pretty(settings)?.as_bytes())?;
+    Ok(())
+}
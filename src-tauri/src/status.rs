@@ -0,0 +1,135 @@
+use serde::{Serialize, Deserialize};
+
+use crate::{FileData, SyncFile};
+
+// How a file in the local folder ("files") compares to the last synced baseline ("project", the local .sync) and
+// to the remote manifest ("remote")
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub(crate) enum FileStatus {
+    // Exists in all three places and the hashes are the same
+    Unchanged,
+    // Exists in all three places, the project and files hashes match but the remote changed
+    RemoteModified,
+    // Exists in all three places, the remote and project hashes match but the files changed
+    LocalModified,
+    // Exists in all three places, the remote and files hashes match but the project is out of date
+    BothModified,
+    // Exists in all three places and all three hashes are different
+    Conflict,
+    // Exists in remote, but not in project
+    RemoteAdded,
+    // Exists in remote and project, but not in files
+    LocalDeleted,
+    // Exists in project and files, but not in remote
+    LocalAdded,
+    // Exists in files, but not in project or remote
+    Untracked,
+}
+
+impl FileStatus {
+    // Statuses that commit pushes, anything else would put an outdated local copy over a newer remote one
+    pub fn is_local_change(self) -> bool {
+        matches!(self, FileStatus::LocalModified | FileStatus::BothModified | FileStatus::LocalDeleted | FileStatus::LocalAdded | FileStatus::Untracked)
+    }
+}
+
+pub(crate) fn file_status(local: Option<&SyncFile>, project: Option<&SyncFile>, remote: Option<&SyncFile>) -> FileStatus {
+    match (local, project, remote) {
+        (Some(lf), Some(pf), Some(rf)) => {
+            if lf.sha256 == pf.sha256 && pf.sha256 == rf.sha256 {
+                FileStatus::Unchanged
+            } else if lf.sha256 == pf.sha256 {
+                FileStatus::RemoteModified
+            } else if pf.sha256 == rf.sha256 {
+                FileStatus::LocalModified
+            } else if lf.sha256 == rf.sha256 {
+                FileStatus::BothModified
+            } else {
+                FileStatus::Conflict
+            }
+        }
+        (_, None, Some(_)) => FileStatus::RemoteAdded,
+        (None, Some(_), Some(_)) => FileStatus::LocalDeleted,
+        (Some(_), Some(_), None) => FileStatus::LocalAdded,
+        (Some(_), None, None) => FileStatus::Untracked,
+        // Gone from both the folder and the remote, nothing left to sync
+        (None, _, None) => FileStatus::Unchanged,
+    }
+}
+
+// Folders carry no hash, so only whether they exist in each place matters. Folders present everywhere are left out
+pub(crate) fn folder_statuses(local: &[String], project: &[String], remote: &[String]) -> Vec<FileData> {
+    let mut all: Vec<&String> = local.iter().chain(project.iter()).chain(remote.iter()).collect();
+    all.sort();
+    all.dedup();
+
+    all.into_iter()
+        .filter_map(|folder| {
+            let status = match (local.contains(folder), project.contains(folder), remote.contains(folder)) {
+                (false, false, true) => FileStatus::RemoteAdded,
+                (false, true, true) => FileStatus::LocalDeleted,
+                (true, true, false) => FileStatus::LocalAdded,
+                (true, false, false) => FileStatus::Untracked,
+                _ => return None,
+            };
+
+            Some(FileData { name: folder.clone(), select: false, path: folder.clone(), status, lock: None, modified_by: None })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testproject::synced_file;
+
+    fn status_of(local: Option<&[u8]>, project: Option<&[u8]>, remote: Option<&[u8]>) -> FileStatus {
+        let [local, project, remote] = [local, project, remote].map(|v| v.map(|c| synced_file("part.sldprt", c)));
+        file_status(local.as_ref(), project.as_ref(), remote.as_ref())
+    }
+
+    #[test]
+    fn files_changed_in_one_place_show_where() {
+        assert_eq!(status_of(Some(b"a"), Some(b"a"), Some(b"a")), FileStatus::Unchanged);
+        assert_eq!(status_of(Some(b"a"), Some(b"a"), Some(b"b")), FileStatus::RemoteModified);
+        assert_eq!(status_of(Some(b"b"), Some(b"a"), Some(b"a")), FileStatus::LocalModified);
+        // Both sides made the same change, only the baseline is behind
+        assert_eq!(status_of(Some(b"b"), Some(b"a"), Some(b"b")), FileStatus::BothModified);
+        assert_eq!(status_of(Some(b"b"), Some(b"a"), Some(b"c")), FileStatus::Conflict);
+    }
+
+    #[test]
+    fn files_missing_from_one_place_show_where() {
+        assert_eq!(status_of(None, None, Some(b"a")), FileStatus::RemoteAdded);
+        assert_eq!(status_of(Some(b"b"), None, Some(b"a")), FileStatus::RemoteAdded);
+        assert_eq!(status_of(None, Some(b"a"), Some(b"a")), FileStatus::LocalDeleted);
+        assert_eq!(status_of(Some(b"a"), Some(b"a"), None), FileStatus::LocalAdded);
+        assert_eq!(status_of(Some(b"a"), None, None), FileStatus::Untracked);
+        assert_eq!(status_of(None, Some(b"a"), None), FileStatus::Unchanged);
+    }
+
+    #[test]
+    fn only_local_changes_get_committed() {
+        assert!(FileStatus::LocalModified.is_local_change());
+        assert!(FileStatus::Untracked.is_local_change());
+        assert!(!FileStatus::RemoteModified.is_local_change());
+        assert!(!FileStatus::Conflict.is_local_change());
+    }
+
+    #[test]
+    fn folders_everywhere_are_left_out() {
+        let folders = |v: &[&str]| v.iter().map(|f| f.to_string()).collect::<Vec<String>>();
+        let local = folders(&["parts", "drawings", "new"]);
+        let project = folders(&["parts", "drawings", "old"]);
+        let remote = folders(&["parts", "old", "theirs"]);
+
+        let statuses: Vec<(String, FileStatus)> = folder_statuses(&local, &project, &remote).into_iter().map(|fd| (fd.path, fd.status)).collect();
+
+        assert_eq!(statuses, vec![
+            ("drawings".to_string(), FileStatus::LocalAdded),
+            ("new".to_string(), FileStatus::Untracked),
+            ("old".to_string(), FileStatus::LocalDeleted),
+            ("theirs".to_string(), FileStatus::RemoteAdded),
+        ]);
+    }
+}
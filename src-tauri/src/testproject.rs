@@ -0,0 +1,79 @@
+use std::{fs, path::{Path, PathBuf}};
+
+use sha2::{Digest, Sha256};
+
+use crate::{FileData, SyncFile, SyncInfo, write_sync_file};
+use crate::status::FileStatus;
+
+// The name every test project goes by, its baseline is test.sync
+pub(crate) const PROJECT: &str = "test";
+
+// A folder in the temp dir that is removed again once the test is done with it, even if it panicked
+pub(crate) struct TestDir {
+    pub path: PathBuf,
+}
+
+impl TestDir {
+    pub fn new() -> TestDir {
+        let path = std::env::temp_dir().join(format!("entangle-test-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&path).unwrap();
+        TestDir { path }
+    }
+
+    // A project folder synced up to baseline
+    pub fn project(baseline: &SyncInfo) -> TestDir {
+        let dir = TestDir::new();
+        dir.write_sync(baseline);
+        dir
+    }
+
+    pub fn write(&self, path: &str, contents: &[u8]) {
+        let pth = self.path.join(path);
+        fs::create_dir_all(pth.parent().unwrap()).unwrap();
+        fs::write(pth, contents).unwrap();
+    }
+
+    pub fn read(&self, path: &str) -> Option<Vec<u8>> {
+        fs::read(self.path.join(path)).ok()
+    }
+
+    pub fn write_sync(&self, sync_info: &SyncInfo) {
+        write_sync_file(&self.path.join(format!("{}.sync", PROJECT)), &serde_json::to_string(sync_info).unwrap()).unwrap();
+    }
+}
+
+impl Drop for TestDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+pub(crate) fn sha256_of(data: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(data))
+}
+
+// A manifest entry for path, with no contents to speak of
+pub(crate) fn remote_file(path: &str) -> SyncFile {
+    SyncFile {
+        name: Path::new(path).file_name().unwrap().to_string_lossy().into_owned(),
+        path: path.to_string(),
+        sha256: String::new(),
+        modified_by: None,
+    }
+}
+
+// A manifest entry for path holding contents
+pub(crate) fn synced_file(path: &str, contents: &[u8]) -> SyncFile {
+    SyncFile { sha256: sha256_of(contents), ..remote_file(path) }
+}
+
+pub(crate) fn selected(path: &str, status: FileStatus) -> FileData {
+    FileData {
+        name: Path::new(path).file_name().unwrap().to_string_lossy().into_owned(),
+        select: true,
+        path: path.to_string(),
+        status,
+        lock: None,
+        modified_by: None,
+    }
+}